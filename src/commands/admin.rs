@@ -0,0 +1,204 @@
+use std::time::Duration;
+
+use color_eyre::eyre::{eyre, Result};
+use hackthebot::{
+    cached_solve_count,
+    htb::api::HtbApi,
+    scheduler::{Job, JobRun, Scheduler},
+    TEAM_MEMBERS,
+};
+use serenity::{
+    builder::{CreateApplicationCommand, EditInteractionResponse},
+    model::{
+        application::{
+            command::CommandOptionType,
+            interaction::application_command::ApplicationCommandInteraction,
+        },
+        Permissions,
+    },
+};
+
+use super::{message, SharedData};
+use crate::discord_utils::truncate;
+use crate::tasks::{RANK_JOB, SOLVES_JOB};
+
+const SUBCOMMANDS: [(&str, &str); 6] = [
+    ("pause", "Stops announcing new solves"),
+    ("resume", "Starts announcing new solves again"),
+    ("poll", "Checks HTB for new solves right now"),
+    (
+        "refresh-rank",
+        "Updates the team rank in the channel topic right now",
+    ),
+    ("status", "Shows what the bot is up to"),
+    ("relogin", "Logs in to HTB again"),
+];
+
+pub fn register(command: &mut CreateApplicationCommand) -> &mut CreateApplicationCommand {
+    command
+        .name("admin")
+        .description("Commands for the owners and admins of the bot")
+        // Only hides the command from non-admins, the owners and admin role are still checked on use.
+        .default_member_permissions(Permissions::ADMINISTRATOR);
+
+    for (name, description) in SUBCOMMANDS {
+        command.create_option(|option| {
+            option
+                .name(name)
+                .description(description)
+                .kind(CommandOptionType::SubCommand)
+        });
+    }
+
+    command
+}
+
+pub async fn run<A: HtbApi>(
+    command: &ApplicationCommandInteraction,
+    data: &SharedData<A>,
+    scheduler: &Scheduler,
+) -> Result<EditInteractionResponse> {
+    let subcommand = command
+        .data
+        .options
+        .first()
+        .map(|option| option.name.as_str())
+        .ok_or_else(|| eyre!("No subcommand was given"))?;
+
+    let solves_job = scheduler
+        .job(SOLVES_JOB)
+        .ok_or_else(|| eyre!("The solve poller isn't scheduled"))?;
+
+    match subcommand {
+        "pause" => {
+            solves_job.set_paused(true);
+            Ok(message("⏸️ Paused announcing new solves."))
+        }
+        "resume" => {
+            solves_job.set_paused(false);
+            Ok(message("▶️ Resumed announcing new solves."))
+        }
+        "poll" => {
+            let summary = scheduler.run_now(SOLVES_JOB).await?;
+            Ok(message(format!("📥 Polled HTB, {summary}.")))
+        }
+        "refresh-rank" => {
+            scheduler.run_now(RANK_JOB).await?;
+            Ok(message("📊 Updated the team rank."))
+        }
+        "status" => Ok(status(data, scheduler).await),
+        "relogin" => {
+            data.lock().await.htb_api.relogin().await?;
+            Ok(message("🔑 Logged in to HTB again."))
+        }
+        name => Err(eyre!("Unknown subcommand {name}")),
+    }
+}
+
+fn format_interval(interval: Duration) -> String {
+    match interval.as_secs() {
+        secs if secs % (60 * 60) == 0 => format!("{}h", secs / (60 * 60)),
+        secs if secs % 60 == 0 => format!("{}m", secs / 60),
+        secs => format!("{secs}s"),
+    }
+}
+
+fn format_run(run: &JobRun) -> String {
+    let when = format!(
+        "<t:{}:R> (took {:.1?})",
+        run.finished_at.timestamp(),
+        run.took
+    );
+
+    match &run.outcome {
+        Ok(summary) => format!("{when}, {summary}"),
+        Err(why) => format!("{when}, failed: {}", truncate(why, 200)),
+    }
+}
+
+fn job_text(job: &Job) -> String {
+    let state = if job.is_running() {
+        "🔄 Running now".to_owned()
+    } else if job.is_paused() {
+        "⏸️ Paused".to_owned()
+    } else {
+        format!("▶️ Every {}", format_interval(job.interval()))
+    };
+
+    let last_run = job
+        .last_run()
+        .map_or_else(|| "Not yet".to_owned(), |run| format_run(&run));
+
+    let mut lines = vec![state, format!("Last run: {last_run}")];
+
+    if let Some(error) = job.last_error() {
+        lines.push(format!("Last error: {}", format_run(&error)));
+    }
+
+    lines.join("\n")
+}
+
+async fn status<A: HtbApi>(data: &SharedData<A>, scheduler: &Scheduler) -> EditInteractionResponse {
+    let (token_expiry, retry_count) = {
+        let data = data.lock().await;
+        (data.htb_api.jwt().exp, data.htb_api.retry_count())
+    };
+
+    let mut response = EditInteractionResponse::default();
+    response.embed(|e| {
+        e.title("🛠️ Status");
+        e.field("Cached solves", cached_solve_count(), true);
+        e.field("Team members", TEAM_MEMBERS.len(), true);
+        e.field("HTB retries", retry_count, true);
+
+        for job in scheduler.jobs() {
+            e.field(format!("⏱️ {} job", job.name()), job_text(job), false);
+        }
+
+        #[allow(clippy::cast_possible_truncation)]
+        e.field(
+            "HTB token expires",
+            format!("<t:{}:R>", token_expiry as i64),
+            false,
+        );
+
+        e
+    });
+
+    response
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::{Local, TimeZone};
+
+    use super::*;
+
+    #[test]
+    fn test_format_interval() {
+        assert_eq!(format_interval(Duration::from_secs(90)), "90s");
+        assert_eq!(format_interval(Duration::from_secs(60)), "1m");
+        assert_eq!(format_interval(Duration::from_secs(24 * 60 * 60)), "24h");
+    }
+
+    #[test]
+    fn test_format_run() {
+        let finished_at = Local.timestamp_opt(1_700_000_000, 0).unwrap();
+        let mut run = JobRun {
+            finished_at,
+            took: Duration::from_millis(1234),
+            outcome: Ok("2 new solves".to_owned()),
+        };
+
+        assert_eq!(
+            format_run(&run),
+            "<t:1700000000:R> (took 1.2s), 2 new solves"
+        );
+
+        run.outcome = Err("HTB is down".to_owned());
+        assert_eq!(
+            format_run(&run),
+            "<t:1700000000:R> (took 1.2s), failed: HTB is down"
+        );
+    }
+}
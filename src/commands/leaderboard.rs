@@ -0,0 +1,373 @@
+use std::collections::HashMap;
+
+use color_eyre::eyre::{eyre, Result};
+use hackthebot::htb::{
+    api::HtbApi,
+    api_types::{ListTeamMembersData, RecentTeamSolve},
+    rate_limit::Priority,
+};
+use serenity::{
+    builder::{CreateApplicationCommand, EditInteractionResponse},
+    model::application::{
+        command::CommandOptionType,
+        component::ButtonStyle,
+        interaction::{
+            application_command::ApplicationCommandInteraction,
+            message_component::MessageComponentInteraction,
+        },
+    },
+};
+
+use super::{htb_api, int_option, string_option, SharedData};
+
+const PAGE_SIZE: usize = 10;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Sort {
+    Points,
+    RootOwns,
+    UserOwns,
+    Bloods,
+}
+
+impl Sort {
+    const ALL: [Sort; 4] = [Sort::Points, Sort::RootOwns, Sort::UserOwns, Sort::Bloods];
+
+    fn from_id(id: &str) -> Option<Self> {
+        Self::ALL.into_iter().find(|sort| sort.id() == id)
+    }
+
+    fn id(self) -> &'static str {
+        match self {
+            Sort::Points => "points",
+            Sort::RootOwns => "root",
+            Sort::UserOwns => "user",
+            Sort::Bloods => "bloods",
+        }
+    }
+
+    fn label(self) -> &'static str {
+        match self {
+            Sort::Points => "Points",
+            Sort::RootOwns => "Root owns",
+            Sort::UserOwns => "User owns",
+            Sort::Bloods => "Bloods",
+        }
+    }
+
+    /// `None` when HTB didn't give us the stat, which ranks below everything else.
+    fn score(self, entry: &LeaderboardEntry) -> Option<i64> {
+        match self {
+            Sort::Points => entry.points,
+            Sort::RootOwns => entry.root_owns,
+            Sort::UserOwns => entry.user_owns,
+            Sort::Bloods => entry.bloods,
+        }
+    }
+}
+
+#[derive(Debug, PartialEq, Eq)]
+struct LeaderboardEntry {
+    name: String,
+    points: Option<i64>,
+    root_owns: Option<i64>,
+    user_owns: Option<i64>,
+    bloods: Option<i64>,
+}
+
+pub fn register(command: &mut CreateApplicationCommand) -> &mut CreateApplicationCommand {
+    command
+        .name("leaderboard")
+        .description("Ranks the members of the team")
+        .create_option(|option| {
+            let option = option
+                .name("sort")
+                .description("What to rank members by, defaults to points")
+                .kind(CommandOptionType::String);
+
+            for sort in Sort::ALL {
+                option.add_string_choice(sort.label(), sort.id());
+            }
+
+            option
+        })
+        .create_option(|option| {
+            option
+                .name("period")
+                .description("Only count solves from a recent period, defaults to all time")
+                .kind(CommandOptionType::Integer)
+                .add_int_choice("All time", 0)
+                .add_int_choice("Last 7 days", 7)
+                .add_int_choice("Last 30 days", 30)
+                .add_int_choice("Last 90 days", 90)
+        })
+}
+
+pub async fn run<A: HtbApi>(
+    command: &ApplicationCommandInteraction,
+    data: &SharedData<A>,
+) -> Result<EditInteractionResponse> {
+    let sort = string_option(command, "sort")
+        .map_or(Some(Sort::Points), Sort::from_id)
+        .ok_or_else(|| eyre!("Unknown sort"))?;
+    let period = u32::try_from(int_option(command, "period").unwrap_or(0))?;
+
+    leaderboard(data, sort, period, 0).await
+}
+
+/// Handles the paging buttons, whose custom ids look like `leaderboard:<sort>:<period>:<page>`.
+pub async fn handle_component<A: HtbApi>(
+    component: &MessageComponentInteraction,
+    data: &SharedData<A>,
+) -> Result<EditInteractionResponse> {
+    let custom_id = &component.data.custom_id;
+    let mut parts = custom_id.split(':').skip(1);
+
+    let (Some(sort), Some(period), Some(page)) = (parts.next(), parts.next(), parts.next()) else {
+        return Err(eyre!("Malformed leaderboard button {custom_id}"));
+    };
+
+    let sort = Sort::from_id(sort).ok_or_else(|| eyre!("Unknown sort {sort}"))?;
+
+    leaderboard(data, sort, period.parse()?, page.parse()?).await
+}
+
+async fn leaderboard<A: HtbApi>(
+    data: &SharedData<A>,
+    sort: Sort,
+    period: u32,
+    page: usize,
+) -> Result<EditInteractionResponse> {
+    let htb_api = htb_api(data, Priority::Low).await?;
+
+    let mut entries = if period == 0 {
+        all_time_entries(&htb_api.list_team_members().await?)
+    } else {
+        let (members, activity) = futures::try_join!(
+            htb_api.list_team_members(),
+            htb_api.get_team_activity(period),
+        )?;
+
+        period_entries(&members, &activity)
+    };
+
+    sort_entries(&mut entries, sort);
+
+    Ok(render(&entries, sort, period, page))
+}
+
+fn all_time_entries(members: &[ListTeamMembersData]) -> Vec<LeaderboardEntry> {
+    members
+        .iter()
+        .map(|member| LeaderboardEntry {
+            name: member.name.clone(),
+            points: member.points.map(i64::from),
+            root_owns: member.root_owns.map(i64::from),
+            user_owns: member.user_owns.map(i64::from),
+            bloods: match (member.root_bloods_count, member.user_bloods_count) {
+                (None, None) => None,
+                (root, user) => Some(i64::from(root.unwrap_or(0)) + i64::from(user.unwrap_or(0))),
+            },
+        })
+        .collect()
+}
+
+/// Tallies up the team activity, so only solves within the period count.
+fn period_entries(
+    members: &[ListTeamMembersData],
+    activity: &[RecentTeamSolve],
+) -> Vec<LeaderboardEntry> {
+    let mut entries = members
+        .iter()
+        .map(|member| {
+            let entry = LeaderboardEntry {
+                name: member.name.clone(),
+                points: Some(0),
+                root_owns: Some(0),
+                user_owns: Some(0),
+                bloods: Some(0),
+            };
+
+            (member.id, entry)
+        })
+        .collect::<HashMap<_, _>>();
+
+    for solve in activity {
+        // Members who have since left the team don't get a spot.
+        let Some(entry) = entries.get_mut(&solve.user.id) else {
+            continue;
+        };
+
+        tally(&mut entry.points, solve.points);
+
+        match solve.solve_type.to_lowercase().as_str() {
+            "root" => tally(&mut entry.root_owns, 1),
+            "user" => tally(&mut entry.user_owns, 1),
+            _ => {}
+        }
+
+        if solve.first_blood {
+            tally(&mut entry.bloods, 1);
+        }
+    }
+
+    entries.into_values().collect()
+}
+
+fn tally(stat: &mut Option<i64>, amount: i64) {
+    *stat = Some(stat.unwrap_or(0) + amount);
+}
+
+fn sort_entries(entries: &mut [LeaderboardEntry], sort: Sort) {
+    entries.sort_by(|a, b| {
+        sort.score(b)
+            .cmp(&sort.score(a))
+            .then_with(|| a.name.to_lowercase().cmp(&b.name.to_lowercase()))
+    });
+}
+
+fn render(
+    entries: &[LeaderboardEntry],
+    sort: Sort,
+    period: u32,
+    page: usize,
+) -> EditInteractionResponse {
+    let num_pages = entries.len().div_ceil(PAGE_SIZE).max(1);
+    let page = page.min(num_pages - 1);
+
+    let lines = entries
+        .iter()
+        .enumerate()
+        .skip(page * PAGE_SIZE)
+        .take(PAGE_SIZE)
+        .map(|(position, entry)| {
+            format!(
+                "`#{}` **{}**: {}",
+                position + 1,
+                entry.name,
+                stats_text(entry, sort)
+            )
+        })
+        .collect::<Vec<_>>();
+
+    let period_text = match period {
+        0 => "all time".to_owned(),
+        days => format!("last {days} days"),
+    };
+
+    let mut response = EditInteractionResponse::default();
+    response.embed(|e| {
+        e.title(format!(
+            "🏆 Leaderboard by {} ({period_text})",
+            sort.label().to_lowercase()
+        ));
+        e.description(lines.join("\n"));
+        e.footer(|f| f.text(format!("Page {} of {num_pages}", page + 1)));
+
+        e
+    });
+    response.components(|c| {
+        c.create_action_row(|row| {
+            row.create_button(|button| {
+                button
+                    .custom_id(custom_id(sort, period, page.saturating_sub(1)))
+                    .label("◀ Previous")
+                    .style(ButtonStyle::Secondary)
+                    .disabled(page == 0)
+            })
+            .create_button(|button| {
+                button
+                    .custom_id(custom_id(sort, period, page + 1))
+                    .label("Next ▶")
+                    .style(ButtonStyle::Secondary)
+                    .disabled(page + 1 >= num_pages)
+            })
+        })
+    });
+
+    response
+}
+
+fn custom_id(sort: Sort, period: u32, page: usize) -> String {
+    format!("leaderboard:{}:{period}:{page}", sort.id())
+}
+
+fn stats_text(entry: &LeaderboardEntry, sort: Sort) -> String {
+    Sort::ALL
+        .into_iter()
+        .map(|stat| {
+            let score = stat
+                .score(entry)
+                .map_or_else(|| "-".to_owned(), |score| score.to_string());
+            let text = format!("{score} {}", stat.label().to_lowercase());
+
+            if stat == sort {
+                format!("__{text}__")
+            } else {
+                text
+            }
+        })
+        .collect::<Vec<_>>()
+        .join(" · ")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn team_members() -> Vec<ListTeamMembersData> {
+        let data = include_str!("../../test_resources/get_team_members.json");
+        serde_json::from_str(data).unwrap()
+    }
+
+    #[test]
+    fn test_all_time_sorting() {
+        let mut entries = all_time_entries(&team_members());
+
+        sort_entries(&mut entries, Sort::Points);
+        assert_eq!(entries[0].name, "MasterAge");
+        assert_eq!(entries[1].name, "wulfgarpro");
+
+        sort_entries(&mut entries, Sort::RootOwns);
+        assert_eq!(entries[0].name, "wulfgarpro");
+        assert_eq!(entries[0].root_owns, Some(64));
+    }
+
+    #[test]
+    fn test_missing_stats_rank_last_and_show_a_dash() {
+        let mut members = team_members();
+        members[0].points = None;
+        members[0].root_bloods_count = None;
+        members[0].user_bloods_count = None;
+
+        let mut entries = all_time_entries(&members);
+        sort_entries(&mut entries, Sort::Points);
+
+        let last = entries.last().unwrap();
+        assert_eq!(last.name, "wulfgarpro");
+        assert_eq!(last.bloods, None);
+        assert!(stats_text(last, Sort::Points).starts_with("__- points__"));
+    }
+
+    #[test]
+    fn test_period_entries_tally_activity() {
+        let data = include_str!("../../test_resources/recent_activity.json");
+        let activity: Vec<RecentTeamSolve> = serde_json::from_str(data).unwrap();
+
+        let mut entries = period_entries(&team_members(), &activity);
+        sort_entries(&mut entries, Sort::Points);
+
+        assert_eq!(entries.len(), 11);
+        assert_eq!(
+            entries[0],
+            LeaderboardEntry {
+                name: "wulfgarpro".to_owned(),
+                points: Some(33),
+                root_owns: Some(1),
+                user_owns: Some(0),
+                bloods: Some(0),
+            }
+        );
+        assert_eq!(entries[1].points, Some(0));
+    }
+}
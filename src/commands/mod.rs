@@ -0,0 +1,248 @@
+use std::{collections::HashSet, sync::Arc};
+
+use async_mutex::Mutex;
+use color_eyre::eyre::{eyre, Error, Result};
+use hackthebot::{
+    htb::{api::HtbApi, api_types::HTBApiClient, error::HtbApiError, rate_limit::Priority},
+    scheduler::Scheduler,
+    ScheduleRunnerData,
+};
+use serde_json::Value;
+use serenity::{
+    builder::{CreateApplicationCommands, EditInteractionResponse},
+    client::Context,
+    http::Http,
+    model::{
+        application::{
+            command::Command,
+            interaction::{
+                application_command::ApplicationCommandInteraction,
+                message_component::MessageComponentInteraction, Interaction,
+            },
+        },
+        guild::Member,
+        id::{GuildId, RoleId, UserId},
+        user::User,
+    },
+};
+
+mod admin;
+mod leaderboard;
+mod ping;
+mod team;
+mod whois;
+
+pub type SharedData<A = HTBApiClient> = Arc<Mutex<ScheduleRunnerData<A>>>;
+
+/// Who is allowed to run `/admin`, either one of the owners or anyone with the admin role.
+#[derive(Debug, Clone, Default)]
+pub struct Admins {
+    pub owners: HashSet<UserId>,
+    pub role: Option<RoleId>,
+}
+
+impl Admins {
+    pub fn allows(&self, user: &User, member: Option<&Member>) -> bool {
+        if self.owners.contains(&user.id) {
+            return true;
+        }
+
+        match (self.role, member) {
+            (Some(role), Some(member)) => member.roles.contains(&role),
+            _ => false,
+        }
+    }
+}
+
+fn create_commands(commands: &mut CreateApplicationCommands) -> &mut CreateApplicationCommands {
+    commands
+        .create_application_command(|command| admin::register(command))
+        .create_application_command(|command| leaderboard::register(command))
+        .create_application_command(|command| ping::register(command))
+        .create_application_command(|command| team::register(command))
+        .create_application_command(|command| whois::register(command))
+}
+
+/// Registers every slash command with discord, either for a single guild or globally.
+///
+/// Guild commands show up immediately, whereas global commands can take up to an hour to propagate.
+pub async fn register_commands(http: &Http, guild_id: Option<GuildId>) -> Result<()> {
+    let registered = match guild_id {
+        Some(guild_id) => {
+            guild_id
+                .set_application_commands(http, create_commands)
+                .await?
+        }
+        None => Command::set_global_application_commands(http, create_commands).await?,
+    };
+
+    log::info!("Registered {} slash commands.", registered.len());
+
+    Ok(())
+}
+
+pub async fn handle_interaction<A: HtbApi>(
+    ctx: &Context,
+    interaction: Interaction,
+    data: &SharedData<A>,
+    admins: &Admins,
+    scheduler: &Scheduler,
+) {
+    match interaction {
+        Interaction::ApplicationCommand(command) => {
+            handle_command(ctx, &command, data, admins, scheduler).await;
+        }
+        Interaction::MessageComponent(component) => handle_component(ctx, &component, data).await,
+        _ => {}
+    }
+}
+
+async fn handle_command<A: HtbApi>(
+    ctx: &Context,
+    command: &ApplicationCommandInteraction,
+    data: &SharedData<A>,
+    admins: &Admins,
+    scheduler: &Scheduler,
+) {
+    // Most commands hit the HTB API, which can easily take longer than the 3 seconds discord allows.
+    if let Err(why) = command.defer(&ctx.http).await {
+        log::error!("Error deferring /{}: {why:?}", command.data.name);
+        return;
+    }
+
+    let response = match command.data.name.as_str() {
+        "admin" if !admins.allows(&command.user, command.member.as_ref()) => Err(eyre!(
+            "Only the owners and admins of the bot can use /admin"
+        )),
+        "admin" => admin::run(command, data, scheduler).await,
+        "leaderboard" => leaderboard::run(command, data).await,
+        "ping" => ping::run(command, data).await,
+        "team" => team::run(command, data).await,
+        "whois" => whois::run(command, data).await,
+        name => Err(eyre!("Unknown command /{name}")),
+    };
+
+    let result = match response {
+        Ok(response) => command
+            .edit_original_interaction_response(&ctx.http, |r| {
+                *r = response;
+                r
+            })
+            .await
+            .map(|_| ()),
+        Err(why) => {
+            log::error!("Error running /{}: {why:?}", command.data.name);
+            reply_with_error(ctx, command, &why).await
+        }
+    };
+
+    if let Err(why) = result {
+        log::error!("Error responding to /{}: {why:?}", command.data.name);
+    }
+}
+
+/// Handles buttons on messages sent by commands, which are routed on the prefix of their custom id.
+async fn handle_component<A: HtbApi>(
+    ctx: &Context,
+    component: &MessageComponentInteraction,
+    data: &SharedData<A>,
+) {
+    let custom_id = &component.data.custom_id;
+
+    if let Err(why) = component.defer(&ctx.http).await {
+        log::error!("Error deferring component {custom_id}: {why:?}");
+        return;
+    }
+
+    let response = match custom_id.split(':').next() {
+        Some("leaderboard") => leaderboard::handle_component(component, data).await,
+        _ => Err(eyre!("Unknown component {custom_id}")),
+    };
+
+    let result = match response {
+        Ok(response) => component
+            .edit_original_interaction_response(&ctx.http, |r| {
+                *r = response;
+                r
+            })
+            .await
+            .map(|_| ()),
+        Err(why) => {
+            log::error!("Error handling component {custom_id}: {why:?}");
+            component
+                .create_followup_message(&ctx.http, |message| {
+                    message.ephemeral(true).content(error_text(&why))
+                })
+                .await
+                .map(|_| ())
+        }
+    };
+
+    if let Err(why) = result {
+        log::error!("Error responding to component {custom_id}: {why:?}");
+    }
+}
+
+/// Replaces the deferred response with an error only the person who ran the command can see.
+async fn reply_with_error(
+    ctx: &Context,
+    command: &ApplicationCommandInteraction,
+    why: &Error,
+) -> serenity::Result<()> {
+    command
+        .delete_original_interaction_response(&ctx.http)
+        .await?;
+    command
+        .create_followup_message(&ctx.http, |message| {
+            message.ephemeral(true).content(error_text(why))
+        })
+        .await?;
+
+    Ok(())
+}
+
+fn error_text(why: &Error) -> String {
+    format!("⚠️ Something went wrong: {why}")
+}
+
+/// Hands out a copy of the HTB client with a fresh token, so the lock isn't held while talking to HTB.
+///
+/// Commands ask for `Priority::Low`, so their lookups can't hold up polling for solves.
+pub async fn htb_api<A: HtbApi>(
+    data: &SharedData<A>,
+    priority: Priority,
+) -> Result<A, HtbApiError> {
+    let mut data = data.lock().await;
+    data.htb_api.handle_token_renewal().await?;
+
+    Ok(data.htb_api.with_priority(priority))
+}
+
+pub fn string_option<'a>(
+    command: &'a ApplicationCommandInteraction,
+    name: &str,
+) -> Option<&'a str> {
+    command
+        .data
+        .options
+        .iter()
+        .find(|option| option.name == name)
+        .and_then(|option| option.value.as_ref())
+        .and_then(Value::as_str)
+}
+
+pub fn int_option(command: &ApplicationCommandInteraction, name: &str) -> Option<i64> {
+    command
+        .data
+        .options
+        .iter()
+        .find(|option| option.name == name)
+        .and_then(|option| option.value.as_ref())
+        .and_then(Value::as_i64)
+}
+
+pub fn message(content: impl ToString) -> EditInteractionResponse {
+    let mut response = EditInteractionResponse::default();
+    response.content(content);
+    response
+}
@@ -0,0 +1,32 @@
+use std::time::Instant;
+
+use color_eyre::eyre::Result;
+use hackthebot::htb::{api::HtbApi, rate_limit::Priority};
+use serenity::{
+    builder::{CreateApplicationCommand, EditInteractionResponse},
+    model::application::interaction::application_command::ApplicationCommandInteraction,
+};
+
+use super::{htb_api, message, SharedData};
+
+pub fn register(command: &mut CreateApplicationCommand) -> &mut CreateApplicationCommand {
+    command
+        .name("ping")
+        .description("Checks that the bot is alive and can reach HTB")
+}
+
+pub async fn run<A: HtbApi>(
+    _command: &ApplicationCommandInteraction,
+    data: &SharedData<A>,
+) -> Result<EditInteractionResponse> {
+    let htb_api = htb_api(data, Priority::Low).await?;
+
+    let started = Instant::now();
+    htb_api.get_team_rank().await?;
+    let elapsed = started.elapsed();
+
+    Ok(message(format!(
+        "🏓 Pong! HTB responded in {}ms.",
+        elapsed.as_millis()
+    )))
+}
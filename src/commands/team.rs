@@ -0,0 +1,61 @@
+use color_eyre::eyre::Result;
+use hackthebot::htb::{api::HtbApi, rate_limit::Priority};
+use serenity::{
+    builder::{CreateApplicationCommand, EditInteractionResponse},
+    model::application::interaction::application_command::ApplicationCommandInteraction,
+};
+
+use super::{htb_api, SharedData};
+
+pub fn register(command: &mut CreateApplicationCommand) -> &mut CreateApplicationCommand {
+    command
+        .name("team")
+        .description("Shows how the team is doing on HTB")
+}
+
+pub async fn run<A: HtbApi>(
+    _command: &ApplicationCommandInteraction,
+    data: &SharedData<A>,
+) -> Result<EditInteractionResponse> {
+    let htb_api = htb_api(data, Priority::Low).await?;
+
+    let (stats, rank, members) = futures::try_join!(
+        htb_api.get_team_statistics(),
+        htb_api.get_team_rank(),
+        htb_api.list_team_members(),
+    )?;
+    let rank = rank.data;
+
+    let mut response = EditInteractionResponse::default();
+    response.embed(|e| {
+        e.title("👥 Team statistics");
+
+        e.field("🏆 Rank", format!("#{}", rank.rank), true);
+        e.field("💰 Points", rank.points, true);
+        e.field("🧑‍💻 Members", members.len(), true);
+        e.field("👤 User owns", stats.user_owns, true);
+        e.field("🖥️ System owns", stats.system_owns, true);
+
+        if let Some(bracket) = &rank.current_bracket {
+            e.field("📊 Bracket", bracket, true);
+        }
+
+        match (&rank.next_bracket, rank.points_for_next_bracket) {
+            (Some(next_bracket), Some(points_to_go)) => {
+                e.field(
+                    "⬆️ Next bracket",
+                    format!("{next_bracket}, {points_to_go} points to go"),
+                    false,
+                );
+            }
+            (Some(next_bracket), None) => {
+                e.field("⬆️ Next bracket", next_bracket, false);
+            }
+            _ => {}
+        }
+
+        e
+    });
+
+    Ok(response)
+}
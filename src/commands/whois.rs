@@ -0,0 +1,167 @@
+use color_eyre::eyre::{eyre, Result};
+use hackthebot::htb::{
+    api::HtbApi,
+    api_types::{ActivityData, UserOverviewData},
+    rate_limit::Priority,
+};
+use serenity::{
+    builder::{CreateApplicationCommand, EditInteractionResponse},
+    model::application::{
+        command::CommandOptionType, interaction::application_command::ApplicationCommandInteraction,
+    },
+};
+
+use super::{htb_api, string_option, SharedData};
+
+pub fn register(command: &mut CreateApplicationCommand) -> &mut CreateApplicationCommand {
+    command
+        .name("whois")
+        .description("Shows the HTB profile of a user")
+        .create_option(|option| {
+            option
+                .name("user")
+                .description("An HTB username or user id")
+                .kind(CommandOptionType::String)
+                .required(true)
+        })
+}
+
+pub async fn run<A: HtbApi>(
+    command: &ApplicationCommandInteraction,
+    data: &SharedData<A>,
+) -> Result<EditInteractionResponse> {
+    let user = string_option(command, "user").ok_or_else(|| eyre!("No user was given"))?;
+    let htb_api = htb_api(data, Priority::Low).await?;
+
+    let user_id = resolve_user_id(&htb_api, user).await?;
+
+    let (overview, activity) = futures::try_join!(
+        htb_api.get_user_overview(user_id),
+        htb_api.get_user_activity(user_id.into()),
+    )?;
+
+    let mut response = EditInteractionResponse::default();
+    response.embed(|e| {
+        let profile = &overview.profile;
+
+        e.title(&profile.name);
+        e.url(htb_api.config().profile_url(profile.id));
+
+        if let Some(avatar) = &profile.avatar {
+            e.thumbnail(htb_api.config().avatar_url(avatar));
+        }
+
+        e.field("🎖️ Rank", rank_text(profile), true);
+        e.field("💰 Points", profile.points, true);
+        e.field("⭐ Respect", profile.respects, true);
+        e.field(
+            "🏴 Owns",
+            format!(
+                "{} user / {} system",
+                profile.user_owns, profile.system_owns
+            ),
+            true,
+        );
+        e.field(
+            "🩸 Bloods",
+            format!(
+                "{} user / {} system",
+                profile.user_bloods, profile.system_bloods
+            ),
+            true,
+        );
+
+        if let Some(team) = &profile.team {
+            let team_text = match team.ranking {
+                Some(ranking) => format!("{} (#{ranking})", team.name),
+                None => team.name.clone(),
+            };
+            e.field("👥 Team", team_text, true);
+        }
+
+        if let Some(latest) = activity.profile.activity.first() {
+            e.field("🕑 Latest activity", activity_text(latest), false);
+        }
+
+        e
+    });
+
+    Ok(response)
+}
+
+/// Accepts either a numeric HTB user id or a username, checking the team before searching
+/// the rest of HTB.
+async fn resolve_user_id(htb_api: &impl HtbApi, user: &str) -> Result<i32> {
+    let user = user.trim();
+
+    if let Ok(user_id) = user.parse::<i32>() {
+        return Ok(user_id);
+    }
+
+    let members = htb_api.list_team_members().await?;
+    if let Some(member) = members
+        .iter()
+        .find(|member| member.name.eq_ignore_ascii_case(user))
+    {
+        return Ok(i32::try_from(member.id)?);
+    }
+
+    // The search matches on part of the name, so only an exact match is taken.
+    let found = htb_api.search_users(user).await?;
+
+    found
+        .iter()
+        .find(|found| found.name.eq_ignore_ascii_case(user))
+        .map(|found| i32::try_from(found.id))
+        .transpose()?
+        .ok_or_else(|| eyre!("Couldn't find an HTB user called {user}"))
+}
+
+fn rank_text(profile: &UserOverviewData) -> String {
+    let mut lines = vec![match profile.ranking {
+        Some(ranking) => format!("{} (#{ranking})", profile.rank),
+        None => profile.rank.clone(),
+    }];
+
+    if let (Some(progress), Some(next_rank)) = (profile.current_rank_progress, &profile.next_rank) {
+        lines.push(format!("{progress:.1}% towards {next_rank}"));
+    }
+
+    lines.join("\n")
+}
+
+fn activity_text(activity: &ActivityData) -> String {
+    // Dates come through as e.g. 2021-07-14T12:47:47.000000Z, the day is all that's interesting.
+    let date = activity.date.split('T').next().unwrap_or(&activity.date);
+
+    if activity.object_type.eq_ignore_ascii_case("challenge") {
+        format!(
+            "Solved {} for {} points on {date}",
+            activity.name, activity.points
+        )
+    } else {
+        format!(
+            "Owned {} on {} for {} points on {date}",
+            activity.solve_type, activity.name, activity.points
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use hackthebot::htb::fake::FakeHtbApi;
+
+    use super::*;
+
+    #[tokio::test]
+    async fn test_resolve_user_id() {
+        let fake = FakeHtbApi::new();
+
+        assert_eq!(resolve_user_id(&fake, "508037").await.unwrap(), 508_037);
+        assert_eq!(resolve_user_id(&fake, " WulfgarPro ").await.unwrap(), 66487);
+        assert_eq!(resolve_user_id(&fake, "jordyn").await.unwrap(), 508_037);
+        assert_eq!(resolve_user_id(&fake, "Jordyn2").await.unwrap(), 1_043_991);
+        assert!(resolve_user_id(&fake, "Jord").await.is_err());
+        assert!(resolve_user_id(&fake, "nobody").await.is_err());
+    }
+}
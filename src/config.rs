@@ -0,0 +1,821 @@
+use std::{
+    collections::HashSet,
+    fmt, fs,
+    path::{Path, PathBuf},
+    str::FromStr,
+    time::Duration,
+};
+
+use serde::Deserialize;
+use serenity::model::id::{ChannelId, GuildId, RoleId, UserId};
+
+use crate::{
+    htb::{
+        api_types::{
+            HTBAPIConfig, HtbAuth, DEFAULT_ACTIVITY_WINDOW_DAYS, DEFAULT_API_URL,
+            DEFAULT_AVATAR_URL, DEFAULT_PROFILE_URL, DEFAULT_REQUESTS_PER_MINUTE,
+            DEFAULT_TOKEN_RENEWAL_MARGIN,
+        },
+        retry::RetryPolicy,
+    },
+    polling::{
+        AdaptiveConfig, PollingConfig, DEFAULT_RANK_UPDATE_INTERVAL, DEFAULT_SOLVE_POLL_INTERVAL,
+    },
+    store::DEFAULT_STORE_PATH,
+    DEFAULT_CATCH_UP_THRESHOLD,
+};
+
+/// Read when `CONFIG_PATH` isn't set, if it exists.
+pub static DEFAULT_CONFIG_PATH: &str = "hackthebot.toml";
+
+/// Every problem found with the configuration, so they can all be fixed in one go.
+#[derive(Debug, thiserror::Error)]
+pub struct ConfigErrors(pub Vec<String>);
+
+impl fmt::Display for ConfigErrors {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Found {} problems with the configuration:", self.0.len())?;
+
+        for problem in &self.0 {
+            write!(f, "\n  - {problem}")?;
+        }
+
+        Ok(())
+    }
+}
+
+// Everything is optional here, so missing values are reported along with everything else
+// rather than by serde one at a time.
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+struct RawConfig {
+    discord: RawDiscord,
+    htb: RawHtb,
+    announcements: RawAnnouncements,
+    polling: RawPolling,
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+struct RawDiscord {
+    token: Option<String>,
+    application_id: Option<u64>,
+    owner_ids: Option<Vec<u64>>,
+    admin_role_id: Option<u64>,
+    guild_id: Option<u64>,
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+struct RawHtb {
+    team_id: Option<i32>,
+    app_token: Option<String>,
+    email: Option<String>,
+    password: Option<String>,
+    totp_secret: Option<String>,
+    api_url: Option<String>,
+    avatar_url: Option<String>,
+    profile_url: Option<String>,
+    max_retries: Option<u32>,
+    retry_base_delay_ms: Option<u64>,
+    retry_max_delay_ms: Option<u64>,
+    requests_per_minute: Option<u32>,
+    token_renewal_margin_secs: Option<u64>,
+    activity_window_days: Option<u32>,
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+struct RawAnnouncements {
+    notifier: Option<String>,
+    channel_id: Option<u64>,
+    blood_role_id: Option<u64>,
+    catch_up_threshold: Option<usize>,
+    solve_store_path: Option<PathBuf>,
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+struct RawPolling {
+    solve_interval_secs: Option<u64>,
+    rank_interval_secs: Option<u64>,
+    adaptive: Option<bool>,
+    fast_interval_secs: Option<u64>,
+    slow_interval_secs: Option<u64>,
+    release_window_hours: Option<u64>,
+    quiet_after_hours: Option<u64>,
+}
+
+#[derive(Clone)]
+pub struct DiscordConfig {
+    pub token: String,
+    pub application_id: u64,
+    /// Allowed to use `/admin`, along with anyone with the admin role.
+    pub owners: HashSet<UserId>,
+    pub admin_role: Option<RoleId>,
+    /// Registers the slash commands for just this guild rather than globally.
+    pub guild_id: Option<GuildId>,
+}
+
+// Keeps the token out of the logs when the config is printed.
+impl fmt::Debug for DiscordConfig {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("DiscordConfig")
+            .field("token", &"<redacted>")
+            .field("application_id", &self.application_id)
+            .field("owners", &self.owners)
+            .field("admin_role", &self.admin_role)
+            .field("guild_id", &self.guild_id)
+            .finish()
+    }
+}
+
+/// Where announcements are sent.
+#[derive(Debug, Clone, PartialEq)]
+pub enum NotifierConfig {
+    Discord {
+        channel_id: ChannelId,
+        /// Role pinged whenever a team member takes first blood.
+        blood_role: Option<RoleId>,
+    },
+    /// Only writes announcements to the log.
+    Log,
+}
+
+#[derive(Debug, Clone)]
+pub struct AnnouncementsConfig {
+    pub notifier: NotifierConfig,
+    /// Once more than this many solves are waiting to be announced they're posted as a single digest.
+    pub catch_up_threshold: usize,
+    pub solve_store_path: PathBuf,
+}
+
+#[derive(Debug, Clone)]
+pub struct Config {
+    /// The file the config was read from, if there was one.
+    pub path: Option<PathBuf>,
+    pub discord: DiscordConfig,
+    pub htb: HTBAPIConfig,
+    pub announcements: AnnouncementsConfig,
+    pub polling: PollingConfig,
+}
+
+/// Applies environment variables on top of the config file, noting any that can't be parsed.
+struct EnvOverrides<'a, F> {
+    lookup: F,
+    problems: &'a mut Vec<String>,
+}
+
+impl<F: Fn(&str) -> Option<String>> EnvOverrides<'_, F> {
+    fn string(&mut self, name: &str, target: &mut Option<String>) {
+        if let Some(value) = (self.lookup)(name) {
+            *target = Some(value);
+        }
+    }
+
+    /// Like `string`, but can also be read from the file at `{name}_FILE`, for Docker and Kubernetes secrets.
+    fn secret(&mut self, name: &str, target: &mut Option<String>) {
+        let file_name = format!("{name}_FILE");
+
+        let (value, source) = match ((self.lookup)(name), (self.lookup)(&file_name)) {
+            (Some(_), Some(_)) => {
+                self.problems
+                    .push(format!("Only one of {name} and {file_name} should be set"));
+                return;
+            }
+            (Some(value), None) => (value, name.to_owned()),
+            (None, Some(path)) => match fs::read_to_string(&path) {
+                Ok(contents) => (
+                    contents.trim_end_matches(['\r', '\n']).to_owned(),
+                    format!("{file_name} {path:?}"),
+                ),
+                Err(why) => {
+                    self.problems
+                        .push(format!("Unable to read {file_name} {path:?}: {why}"));
+                    return;
+                }
+            },
+            (None, None) => return,
+        };
+
+        // An empty secret is never right, and usually means a secret wasn't mounted properly.
+        if value.trim().is_empty() {
+            self.problems.push(format!("{source} is empty"));
+        } else {
+            *target = Some(value);
+        }
+    }
+
+    fn parsed<T: FromStr>(&mut self, name: &str, target: &mut Option<T>) {
+        if let Some(value) = (self.lookup)(name) {
+            match value.trim().parse() {
+                Ok(parsed) => *target = Some(parsed),
+                Err(_) => self
+                    .problems
+                    .push(format!("{name} has an invalid value {value:?}")),
+            }
+        }
+    }
+
+    fn list<T: FromStr>(&mut self, name: &str, target: &mut Option<Vec<T>>) {
+        if let Some(value) = (self.lookup)(name) {
+            match value.split(',').map(|item| item.trim().parse()).collect() {
+                Ok(parsed) => *target = Some(parsed),
+                Err(_) => self.problems.push(format!(
+                    "{name} should be a comma separated list, but was {value:?}"
+                )),
+            }
+        }
+    }
+}
+
+fn apply_env(raw: &mut RawConfig, env: &mut EnvOverrides<impl Fn(&str) -> Option<String>>) {
+    let discord = &mut raw.discord;
+    env.secret("DISCORD_TOKEN", &mut discord.token);
+    env.parsed("APPLICATION_ID", &mut discord.application_id);
+    env.list("OWNER_ID", &mut discord.owner_ids);
+    env.parsed("ADMIN_ROLE_ID", &mut discord.admin_role_id);
+    env.parsed("DISCORD_GUILD_ID", &mut discord.guild_id);
+
+    let htb = &mut raw.htb;
+    env.parsed("HTB_TEAM_ID", &mut htb.team_id);
+    env.secret("HTB_APP_TOKEN", &mut htb.app_token);
+    env.string("HTB_EMAIL", &mut htb.email);
+    env.secret("HTB_PASSWORD", &mut htb.password);
+    env.secret("HTB_TOTP_SECRET", &mut htb.totp_secret);
+    env.string("HTB_API_URL", &mut htb.api_url);
+    env.string("HTB_AVATAR_URL", &mut htb.avatar_url);
+    env.string("HTB_PROFILE_URL", &mut htb.profile_url);
+    env.parsed("HTB_MAX_RETRIES", &mut htb.max_retries);
+    env.parsed("HTB_RETRY_BASE_DELAY_MS", &mut htb.retry_base_delay_ms);
+    env.parsed("HTB_RETRY_MAX_DELAY_MS", &mut htb.retry_max_delay_ms);
+    env.parsed("HTB_REQUESTS_PER_MINUTE", &mut htb.requests_per_minute);
+    env.parsed(
+        "HTB_TOKEN_RENEWAL_MARGIN_SECS",
+        &mut htb.token_renewal_margin_secs,
+    );
+    env.parsed("HTB_ACTIVITY_WINDOW_DAYS", &mut htb.activity_window_days);
+
+    let announcements = &mut raw.announcements;
+    env.string("NOTIFIER", &mut announcements.notifier);
+    env.parsed("HTB_CHANNEL_ID", &mut announcements.channel_id);
+    env.parsed("BLOOD_ROLE_ID", &mut announcements.blood_role_id);
+    env.parsed("CATCH_UP_THRESHOLD", &mut announcements.catch_up_threshold);
+    env.parsed("SOLVE_STORE_PATH", &mut announcements.solve_store_path);
+
+    let polling = &mut raw.polling;
+    env.parsed("SOLVE_POLL_INTERVAL_SECS", &mut polling.solve_interval_secs);
+    env.parsed("RANK_UPDATE_INTERVAL_SECS", &mut polling.rank_interval_secs);
+    env.parsed("ADAPTIVE_POLLING", &mut polling.adaptive);
+    env.parsed(
+        "ADAPTIVE_FAST_INTERVAL_SECS",
+        &mut polling.fast_interval_secs,
+    );
+    env.parsed(
+        "ADAPTIVE_SLOW_INTERVAL_SECS",
+        &mut polling.slow_interval_secs,
+    );
+    env.parsed(
+        "ADAPTIVE_RELEASE_WINDOW_HOURS",
+        &mut polling.release_window_hours,
+    );
+    env.parsed("ADAPTIVE_QUIET_AFTER_HOURS", &mut polling.quiet_after_hours);
+}
+
+/// Notes a missing value, pointing at both the config key and the environment variable.
+fn required<T>(value: Option<T>, key: &str, env: &str, problems: &mut Vec<String>) -> Option<T> {
+    if value.is_none() {
+        problems.push(format!("{key} ({env}) is required"));
+    }
+
+    value
+}
+
+fn validate_discord(raw: RawDiscord, problems: &mut Vec<String>) -> Option<DiscordConfig> {
+    let token = required(
+        raw.token.filter(|token| !token.trim().is_empty()),
+        "discord.token",
+        "DISCORD_TOKEN",
+        problems,
+    );
+    let application_id = required(
+        raw.application_id,
+        "discord.application_id",
+        "APPLICATION_ID",
+        problems,
+    );
+    let owner_ids = required(
+        raw.owner_ids.filter(|owner_ids| !owner_ids.is_empty()),
+        "discord.owner_ids",
+        "OWNER_ID",
+        problems,
+    );
+
+    Some(DiscordConfig {
+        token: token?,
+        application_id: application_id?,
+        owners: owner_ids?.into_iter().map(UserId).collect(),
+        admin_role: raw.admin_role_id.map(RoleId),
+        guild_id: raw.guild_id.map(GuildId),
+    })
+}
+
+fn validate_htb(raw: RawHtb, problems: &mut Vec<String>) -> Option<HTBAPIConfig> {
+    let team_id = required(raw.team_id, "htb.team_id", "HTB_TEAM_ID", problems);
+
+    // An app token means nobody's password has to be handed to the bot, so it wins.
+    let auth = match (raw.app_token, raw.email, raw.password) {
+        (Some(token), _, _) => Some(HtbAuth::AppToken(token)),
+        (None, Some(email), Some(password)) => Some(HtbAuth::Password {
+            email,
+            password,
+            totp_secret: raw.totp_secret,
+        }),
+        (None, email, password) => {
+            problems.push(format!(
+                "htb.app_token (HTB_APP_TOKEN) is required, or htb.email (HTB_EMAIL) and htb.password (HTB_PASSWORD) instead, but {} missing",
+                match (email, password) {
+                    (Some(_), None) => "htb.password is",
+                    (None, Some(_)) => "htb.email is",
+                    _ => "both are",
+                }
+            ));
+            None
+        }
+    };
+
+    let api_url = raw.api_url.unwrap_or_else(|| DEFAULT_API_URL.to_owned());
+    let avatar_url = raw
+        .avatar_url
+        .unwrap_or_else(|| DEFAULT_AVATAR_URL.to_owned());
+    let profile_url = raw
+        .profile_url
+        .unwrap_or_else(|| DEFAULT_PROFILE_URL.to_owned());
+    for (key, url) in [
+        ("htb.api_url", &api_url),
+        ("htb.avatar_url", &avatar_url),
+        ("htb.profile_url", &profile_url),
+    ] {
+        if !url.starts_with("http://") && !url.starts_with("https://") {
+            problems.push(format!("{key} should be an http(s) url, but was {url:?}"));
+        }
+    }
+
+    let default_retry = RetryPolicy::default();
+    let retry = RetryPolicy {
+        max_retries: raw.max_retries.unwrap_or(default_retry.max_retries),
+        base_delay: raw
+            .retry_base_delay_ms
+            .map_or(default_retry.base_delay, Duration::from_millis),
+        max_delay: raw
+            .retry_max_delay_ms
+            .map_or(default_retry.max_delay, Duration::from_millis),
+    };
+    if retry.base_delay > retry.max_delay {
+        problems
+            .push("htb.retry_base_delay_ms can't be longer than htb.retry_max_delay_ms".to_owned());
+    }
+
+    let requests_per_minute = raw
+        .requests_per_minute
+        .unwrap_or(DEFAULT_REQUESTS_PER_MINUTE);
+    if requests_per_minute == 0 {
+        problems.push("htb.requests_per_minute should be at least 1".to_owned());
+    }
+
+    let activity_window_days = raw
+        .activity_window_days
+        .unwrap_or(DEFAULT_ACTIVITY_WINDOW_DAYS);
+    if activity_window_days == 0 {
+        problems.push("htb.activity_window_days should be at least 1".to_owned());
+    }
+
+    Some(HTBAPIConfig {
+        auth: auth?,
+        team_id: team_id?,
+        api_url,
+        avatar_url,
+        profile_url,
+        retry,
+        requests_per_minute,
+        token_renewal_margin: raw
+            .token_renewal_margin_secs
+            .map_or(DEFAULT_TOKEN_RENEWAL_MARGIN, Duration::from_secs),
+        activity_window_days,
+    })
+}
+
+fn validate_announcements(
+    raw: RawAnnouncements,
+    problems: &mut Vec<String>,
+) -> Option<AnnouncementsConfig> {
+    let notifier = match raw.notifier.as_deref().unwrap_or("discord") {
+        "discord" => required(
+            raw.channel_id,
+            "announcements.channel_id",
+            "HTB_CHANNEL_ID",
+            problems,
+        )
+        .map(|channel_id| NotifierConfig::Discord {
+            channel_id: ChannelId(channel_id),
+            blood_role: raw.blood_role_id.map(RoleId),
+        }),
+        "log" => Some(NotifierConfig::Log),
+        other => {
+            problems.push(format!(
+                "announcements.notifier should be discord or log, but was {other:?}"
+            ));
+            None
+        }
+    };
+
+    Some(AnnouncementsConfig {
+        notifier: notifier?,
+        catch_up_threshold: raw.catch_up_threshold.unwrap_or(DEFAULT_CATCH_UP_THRESHOLD),
+        solve_store_path: raw
+            .solve_store_path
+            .unwrap_or_else(|| PathBuf::from(DEFAULT_STORE_PATH)),
+    })
+}
+
+/// Reads a duration given in multiples of `unit_secs`, which has to be at least 1 and not overflow.
+fn positive_duration(
+    key: &str,
+    value: Option<u64>,
+    unit_secs: u64,
+    default: Duration,
+    problems: &mut Vec<String>,
+) -> Duration {
+    let Some(value) = value else {
+        return default;
+    };
+
+    if value == 0 {
+        problems.push(format!("{key} should be at least 1"));
+        return default;
+    }
+
+    match value.checked_mul(unit_secs) {
+        Some(secs) => Duration::from_secs(secs),
+        None => {
+            problems.push(format!("{key} is too large, but was {value}"));
+            default
+        }
+    }
+}
+
+fn validate_polling(raw: RawPolling, problems: &mut Vec<String>) -> PollingConfig {
+    const SECOND: u64 = 1;
+    const HOUR: u64 = 60 * 60;
+
+    let mut positive = |key: &str, value: Option<u64>, unit_secs: u64, default: Duration| {
+        positive_duration(key, value, unit_secs, default, problems)
+    };
+
+    let solve_interval = positive(
+        "polling.solve_interval_secs",
+        raw.solve_interval_secs,
+        SECOND,
+        DEFAULT_SOLVE_POLL_INTERVAL,
+    );
+    let rank_interval = positive(
+        "polling.rank_interval_secs",
+        raw.rank_interval_secs,
+        SECOND,
+        DEFAULT_RANK_UPDATE_INTERVAL,
+    );
+
+    let default = AdaptiveConfig::default();
+    let adaptive = AdaptiveConfig {
+        fast_interval: positive(
+            "polling.fast_interval_secs",
+            raw.fast_interval_secs,
+            SECOND,
+            default.fast_interval,
+        ),
+        slow_interval: positive(
+            "polling.slow_interval_secs",
+            raw.slow_interval_secs,
+            SECOND,
+            default.slow_interval,
+        ),
+        release_window: positive(
+            "polling.release_window_hours",
+            raw.release_window_hours,
+            HOUR,
+            default.release_window,
+        ),
+        quiet_after: positive(
+            "polling.quiet_after_hours",
+            raw.quiet_after_hours,
+            HOUR,
+            default.quiet_after,
+        ),
+    };
+
+    let adaptive = raw.adaptive.unwrap_or(false).then_some(adaptive);
+
+    if let Some(adaptive) = &adaptive {
+        if adaptive.fast_interval > solve_interval || solve_interval > adaptive.slow_interval {
+            problems.push(
+                "polling.fast_interval_secs, polling.solve_interval_secs and polling.slow_interval_secs should go from shortest to longest"
+                    .to_owned(),
+            );
+        }
+    }
+
+    PollingConfig {
+        solve_interval,
+        rank_interval,
+        adaptive,
+    }
+}
+
+impl Config {
+    /// Reads the config file at `CONFIG_PATH`, or `hackthebot.toml` if it exists,
+    /// and then applies any environment variables on top.
+    pub fn load(lookup: impl Fn(&str) -> Option<String>) -> Result<Self, ConfigErrors> {
+        let (path, explicit) = match lookup("CONFIG_PATH") {
+            Some(path) => (PathBuf::from(path), true),
+            None => (PathBuf::from(DEFAULT_CONFIG_PATH), false),
+        };
+
+        if !explicit && !path.exists() {
+            return Self::parse(None, None, lookup);
+        }
+
+        let contents = fs::read_to_string(&path).map_err(|why| {
+            ConfigErrors(vec![format!("Unable to read {}: {why}", path.display())])
+        })?;
+
+        Self::parse(Some(&path), Some(&contents), lookup)
+    }
+
+    /// Builds the config from the contents of a config file, if there is one, and the environment.
+    pub fn parse(
+        path: Option<&Path>,
+        contents: Option<&str>,
+        lookup: impl Fn(&str) -> Option<String>,
+    ) -> Result<Self, ConfigErrors> {
+        let mut problems = Vec::new();
+
+        let (mut raw, parsed) = match contents.map(toml::from_str::<RawConfig>) {
+            Some(Ok(raw)) => (raw, true),
+            Some(Err(why)) => {
+                let name =
+                    path.map_or_else(|| "config".to_owned(), |path| path.display().to_string());
+                problems.push(format!("Unable to parse {name}: {}", why.message()));
+                (RawConfig::default(), false)
+            }
+            None => (RawConfig::default(), true),
+        };
+
+        apply_env(
+            &mut raw,
+            &mut EnvOverrides {
+                lookup,
+                problems: &mut problems,
+            },
+        );
+
+        // Anything from the file would be reported as missing, so stop at what can be trusted.
+        if !parsed {
+            return Err(ConfigErrors(problems));
+        }
+
+        let discord = validate_discord(raw.discord, &mut problems);
+        let htb = validate_htb(raw.htb, &mut problems);
+        let announcements = validate_announcements(raw.announcements, &mut problems);
+        let polling = validate_polling(raw.polling, &mut problems);
+
+        match (discord, htb, announcements) {
+            (Some(discord), Some(htb), Some(announcements)) if problems.is_empty() => Ok(Self {
+                path: path.map(Path::to_path_buf),
+                discord,
+                htb,
+                announcements,
+                polling,
+            }),
+            _ => Err(ConfigErrors(problems)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use super::*;
+
+    const FULL_CONFIG: &str = r#"
+        [discord]
+        token = "discord-token"
+        application_id = 1234
+        owner_ids = [1, 2]
+
+        [htb]
+        team_id = 2230
+        email = "bot@example.com"
+        password = "hunter2"
+
+        [announcements]
+        channel_id = 5678
+        blood_role_id = 42
+
+        [polling]
+        solve_interval_secs = 30
+        adaptive = true
+    "#;
+
+    fn env(vars: &[(&str, &str)]) -> impl Fn(&str) -> Option<String> {
+        let vars: HashMap<String, String> = vars
+            .iter()
+            .map(|(name, value)| ((*name).to_owned(), (*value).to_owned()))
+            .collect();
+
+        move |name| vars.get(name).cloned()
+    }
+
+    #[test]
+    fn test_parse_config_file() {
+        let config = Config::parse(None, Some(FULL_CONFIG), env(&[])).unwrap();
+
+        assert_eq!(config.discord.application_id, 1234);
+        assert_eq!(config.discord.owners.len(), 2);
+        assert_eq!(config.htb.team_id, 2230);
+        assert!(matches!(config.htb.auth, HtbAuth::Password { .. }));
+        assert_eq!(config.htb.api_url, DEFAULT_API_URL);
+        assert_eq!(
+            config.announcements.notifier,
+            NotifierConfig::Discord {
+                channel_id: ChannelId(5678),
+                blood_role: Some(RoleId(42)),
+            }
+        );
+        assert_eq!(config.polling.solve_interval, Duration::from_secs(30));
+        assert_eq!(config.polling.adaptive, Some(AdaptiveConfig::default()));
+    }
+
+    #[test]
+    fn test_env_overrides_config_file() {
+        let config = Config::parse(
+            None,
+            Some(FULL_CONFIG),
+            env(&[
+                ("HTB_APP_TOKEN", "app-token"),
+                ("OWNER_ID", "3, 4, 5"),
+                ("NOTIFIER", "log"),
+            ]),
+        )
+        .unwrap();
+
+        assert_eq!(config.discord.owners.len(), 3);
+        assert!(matches!(config.htb.auth, HtbAuth::AppToken(_)));
+        assert_eq!(config.announcements.notifier, NotifierConfig::Log);
+
+        // Environment variables alone are still enough.
+        let config = Config::parse(
+            None,
+            None,
+            env(&[
+                ("DISCORD_TOKEN", "discord-token"),
+                ("APPLICATION_ID", "1234"),
+                ("OWNER_ID", "1"),
+                ("HTB_TEAM_ID", "2230"),
+                ("HTB_APP_TOKEN", "app-token"),
+                ("HTB_CHANNEL_ID", "5678"),
+            ]),
+        );
+        assert!(config.is_ok());
+    }
+
+    #[test]
+    fn test_secrets_from_files() {
+        let secret_dir = tempfile::TempDir::new().unwrap();
+        let secret_path = secret_dir.path().join("secret");
+        fs::write(&secret_path, "app-token\n").unwrap();
+        let secret_path = secret_path.to_str().unwrap();
+
+        let config = Config::parse(
+            None,
+            Some(FULL_CONFIG),
+            env(&[("HTB_APP_TOKEN_FILE", secret_path)]),
+        );
+        assert!(
+            matches!(config.unwrap().htb.auth, HtbAuth::AppToken(token) if token == "app-token")
+        );
+
+        let errors = Config::parse(
+            None,
+            Some(FULL_CONFIG),
+            env(&[
+                ("DISCORD_TOKEN", "discord-token"),
+                ("DISCORD_TOKEN_FILE", secret_path),
+                ("HTB_PASSWORD_FILE", "/nonexistent/password"),
+            ]),
+        )
+        .unwrap_err();
+
+        assert_eq!(errors.0.len(), 2);
+        assert_eq!(
+            errors.0[0],
+            "Only one of DISCORD_TOKEN and DISCORD_TOKEN_FILE should be set"
+        );
+        assert!(
+            errors.0[1].starts_with("Unable to read HTB_PASSWORD_FILE \"/nonexistent/password\"")
+        );
+
+        // A secret that wasn't mounted properly is caught rather than used.
+        fs::write(secret_path, "\n").unwrap();
+        let errors = Config::parse(
+            None,
+            Some(FULL_CONFIG),
+            env(&[("HTB_APP_TOKEN_FILE", secret_path), ("HTB_PASSWORD", " ")]),
+        )
+        .unwrap_err();
+
+        assert_eq!(
+            errors.0,
+            vec![
+                format!("HTB_APP_TOKEN_FILE {secret_path:?} is empty"),
+                "HTB_PASSWORD is empty".to_owned(),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_every_problem_is_reported() {
+        let errors = Config::parse(
+            None,
+            Some("[htb]\nemail = \"bot@example.com\"\nrequests_per_minute = 0"),
+            env(&[
+                ("APPLICATION_ID", "not a number"),
+                ("NOTIFIER", "carrier pigeon"),
+            ]),
+        )
+        .unwrap_err();
+
+        assert_eq!(
+            errors.0,
+            vec![
+                "APPLICATION_ID has an invalid value \"not a number\"",
+                "discord.token (DISCORD_TOKEN) is required",
+                "discord.application_id (APPLICATION_ID) is required",
+                "discord.owner_ids (OWNER_ID) is required",
+                "htb.team_id (HTB_TEAM_ID) is required",
+                "htb.app_token (HTB_APP_TOKEN) is required, or htb.email (HTB_EMAIL) and htb.password (HTB_PASSWORD) instead, but htb.password is missing",
+                "htb.requests_per_minute should be at least 1",
+                "announcements.notifier should be discord or log, but was \"carrier pigeon\"",
+            ]
+        );
+    }
+
+    #[test]
+    fn test_unparseable_file_only_reports_parse_errors() {
+        let errors = Config::parse(
+            None,
+            Some("[discord]\ntoken = \"discord-token\"\napplication_id = "),
+            env(&[("HTB_TEAM_ID", "not a number")]),
+        )
+        .unwrap_err();
+
+        assert_eq!(errors.0.len(), 2);
+        assert!(errors.0[0].starts_with("Unable to parse config"));
+        assert_eq!(
+            errors.0[1],
+            "HTB_TEAM_ID has an invalid value \"not a number\""
+        );
+    }
+
+    #[test]
+    fn test_polling_hours_are_validated() {
+        let errors = Config::parse(
+            None,
+            Some(&format!(
+                "{FULL_CONFIG}release_window_hours = 0\nquiet_after_hours = {}",
+                i64::MAX
+            )),
+            env(&[]),
+        )
+        .unwrap_err();
+
+        assert_eq!(
+            errors.0,
+            vec![
+                "polling.release_window_hours should be at least 1".to_owned(),
+                format!(
+                    "polling.quiet_after_hours is too large, but was {}",
+                    i64::MAX
+                ),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_unknown_keys_are_reported() {
+        let errors =
+            Config::parse(None, Some("[discord]\ntokne = \"oops\""), env(&[])).unwrap_err();
+
+        assert!(errors.0[0].contains("unknown field `tokne`"));
+    }
+}
@@ -1,13 +1,75 @@
-use color_eyre::eyre::Result;
-use hackthebot::Challenge;
-use serenity::{http::Http, model::id::ChannelId};
+use std::{collections::BTreeMap, sync::Arc};
 
+use chrono::{DateTime, Local};
+use color_eyre::eyre::{eyre, Error, Result};
+use hackthebot::{
+    config::{Config, NotifierConfig},
+    htb::api_types::{RankStatsData, RecentTeamSolve, UserData},
+    notifier::{LogNotifier, Notifier, SolveToAnnounce},
+    Challenge,
+};
+use serenity::{
+    async_trait,
+    http::Http,
+    model::id::{ChannelId, MessageId, RoleId},
+    model::mention::Mentionable,
+    utils::Colour,
+};
+
+/// HTB's signature green, used for regular solves.
+const SOLVE_COLOUR: Colour = Colour(0x9f_ef00);
+/// Used for first bloods so they stand out from the regular solves.
+const BLOOD_COLOUR: Colour = Colour(0xe7_4c3c);
+
+/// Sends everything to a discord channel, pinging the blood role for first bloods.
 #[derive(Debug)]
-pub struct SolveToAnnounce {
-    pub solver: String,
-    pub user_id: i64,
-    pub solve_type: String,
-    pub challenge: Challenge,
+pub struct DiscordNotifier {
+    pub http: Http,
+    pub channel_id: ChannelId,
+    /// Role pinged whenever a team member takes first blood.
+    pub blood_role: Option<RoleId>,
+}
+
+#[async_trait]
+impl Notifier for DiscordNotifier {
+    async fn announce_solve(&self, solve: &SolveToAnnounce) -> Result<Option<MessageId>> {
+        let message_id =
+            announce_solve(solve, self.blood_role, &self.channel_id, &self.http).await?;
+
+        Ok(Some(message_id))
+    }
+
+    async fn post_digest(&self, solves: &[RecentTeamSolve]) -> Result<Option<MessageId>> {
+        let message_id = announce_digest(solves, &self.channel_id, &self.http).await?;
+
+        Ok(Some(message_id))
+    }
+
+    async fn welcome_member(&self, user: &UserData, avatar_url: &str) -> Result<()> {
+        welcome_member(user, avatar_url, &self.channel_id, &self.http).await
+    }
+
+    async fn update_status(&self, stats: &RankStatsData) -> Result<()> {
+        update_htb_channel_topic_with_stats(stats, &self.channel_id, &self.http).await
+    }
+}
+
+/// Announces to discord unless configured to only write to the log.
+pub fn notifier_from_config(config: &Config) -> Arc<dyn Notifier> {
+    match config.announcements.notifier {
+        NotifierConfig::Discord {
+            channel_id,
+            blood_role,
+        } => Arc::new(DiscordNotifier {
+            http: Http::new_with_application_id(
+                &config.discord.token,
+                config.discord.application_id,
+            ),
+            channel_id,
+            blood_role,
+        }),
+        NotifierConfig::Log => Arc::new(LogNotifier),
+    }
 }
 
 pub fn get_challenge_category(challenge: &Challenge) -> String {
@@ -21,46 +83,42 @@ pub fn get_challenge_category(challenge: &Challenge) -> String {
     }
 }
 
-fn capitalise_first(s: &str) -> String {
-    let mut c = s.chars();
-    match c.next() {
-        None => String::new(),
-        Some(f) => f.to_uppercase().chain(c).collect(),
+/// The embed colour of a solve and the role to ping, which only first bloods get.
+fn solve_style(solve: &SolveToAnnounce, blood_role: Option<RoleId>) -> (Colour, Option<RoleId>) {
+    if solve.first_blood {
+        (BLOOD_COLOUR, blood_role)
+    } else {
+        (SOLVE_COLOUR, None)
     }
 }
 
 pub async fn announce_solve(
     solve: &SolveToAnnounce,
+    blood_role: Option<RoleId>,
     channel_id: &ChannelId,
     http: &Http,
-) -> Result<()> {
+) -> Result<MessageId> {
     let challenge = &solve.challenge;
     let category = get_challenge_category(challenge);
+    let content = solve.headline();
 
-    let solve_type = capitalise_first(&solve.solve_type);
-
-    // Build up the content based around the solve type
-    let content = if solve_type.eq("Challenge") {
-        format!(
-            "🏴 {} has been solved by {}",
-            &challenge.name, &solve.solver
-        )
-    } else {
-        format!(
-            "🏴 {} has been owned by {} on {}",
-            solve_type, &solve.solver, &challenge.name
-        )
-    };
+    let (colour, ping) = solve_style(solve, blood_role);
 
-    channel_id
+    let message = channel_id
         .send_message(http, |message| {
+            if let Some(role) = ping {
+                message.content(role.mention());
+                message.allowed_mentions(|mentions| mentions.roles(vec![role]));
+            }
+
             message.embed(|e| {
                 e.title(content);
+                e.colour(colour);
                 e.field("📚 Category", &category, true);
                 e.field("💰 Points", challenge.points, true);
 
                 if let Some(avatar) = &solve.challenge.machine_avatar {
-                    e.thumbnail(format!("https://www.hackthebox.eu/{avatar}"));
+                    e.thumbnail(avatar);
                 }
 
                 e
@@ -68,5 +126,268 @@ pub async fn announce_solve(
         })
         .await?;
 
+    log::info!(
+        "Announced {} on {} by {} ({})",
+        solve.solve_type,
+        challenge.name,
+        solve.solver,
+        solve.user_id
+    );
+
+    Ok(message.id)
+}
+
+/// Discord rejects embeds with more fields than this.
+const MAX_EMBED_FIELDS: usize = 25;
+/// Discord rejects embed fields with a longer value than this.
+const MAX_FIELD_LENGTH: usize = 1024;
+/// Discord rejects embeds whose title, description and fields add up to more than this.
+const MAX_EMBED_LENGTH: usize = 6000;
+/// Room kept for the field that says how many members didn't fit.
+const HIDDEN_FIELD_RESERVE: usize = 64;
+
+/// The solve types of each challenge or machine a member solved, keyed by category.
+type SolvesByCategory<'a> = BTreeMap<String, Vec<(&'a str, Vec<&'a str>)>>;
+
+/// Groups solves by member and then by category, producing one embed field per member.
+pub fn digest_fields(solves: &[RecentTeamSolve]) -> Vec<(String, String)> {
+    let mut members: BTreeMap<&str, SolvesByCategory> = BTreeMap::new();
+
+    for solve in solves {
+        let category = get_challenge_category(&Challenge::from(solve));
+        let objects = members
+            .entry(&solve.user.name)
+            .or_default()
+            .entry(category)
+            .or_default();
+
+        match objects.iter_mut().find(|(name, _)| *name == solve.name) {
+            Some((_, solve_types)) => solve_types.push(&solve.solve_type),
+            None => objects.push((&solve.name, vec![&solve.solve_type])),
+        }
+    }
+
+    members
+        .into_iter()
+        .map(|(member, categories)| {
+            let lines = categories
+                .into_iter()
+                .map(|(category, objects)| {
+                    let objects = objects
+                        .into_iter()
+                        .map(|(name, solve_types)| {
+                            if solve_types
+                                .iter()
+                                .all(|t| t.eq_ignore_ascii_case("challenge"))
+                            {
+                                name.to_owned()
+                            } else {
+                                format!("{} ({})", name, solve_types.join(", "))
+                            }
+                        })
+                        .collect::<Vec<_>>();
+
+                    format!("**{}**: {}", category, objects.join(", "))
+                })
+                .collect::<Vec<_>>();
+
+            (
+                member.to_owned(),
+                truncate(&lines.join("\n"), MAX_FIELD_LENGTH),
+            )
+        })
+        .collect()
+}
+
+pub fn truncate(s: &str, max_chars: usize) -> String {
+    if s.chars().count() <= max_chars {
+        return s.to_owned();
+    }
+
+    let mut truncated = s.chars().take(max_chars - 1).collect::<String>();
+    truncated.push('…');
+    truncated
+}
+
+/// Keeps as many fields as Discord allows in an embed that already has `used` characters,
+/// replacing the rest with a field that says how many members were left out.
+fn fit_fields(fields: Vec<(String, String)>, used: usize) -> Vec<(String, String)> {
+    let field_length =
+        |(name, value): &(String, String)| name.chars().count() + value.chars().count();
+
+    let total_length = used + fields.iter().map(field_length).sum::<usize>();
+    if fields.len() <= MAX_EMBED_FIELDS && total_length <= MAX_EMBED_LENGTH {
+        return fields;
+    }
+
+    let num_fields = fields.len();
+    let mut length = used + HIDDEN_FIELD_RESERVE;
+    let kept = fields
+        .into_iter()
+        .take(MAX_EMBED_FIELDS - 1)
+        .take_while(|field| {
+            length += field_length(field);
+            length <= MAX_EMBED_LENGTH
+        })
+        .collect::<Vec<_>>();
+
+    let hidden = num_fields - kept.len();
+    let mut fields = kept;
+    fields.push(("…".to_owned(), format!("and {hidden} more members")));
+    fields
+}
+
+pub async fn announce_digest(
+    solves: &[RecentTeamSolve],
+    channel_id: &ChannelId,
+    http: &Http,
+) -> Result<MessageId> {
+    let points: i64 = solves.iter().map(|solve| solve.points).sum();
+    let title = format!("📰 {} solves while I was away", solves.len());
+    let description = format!("Catching up on {points} points worth of solves.");
+
+    let fields = fit_fields(
+        digest_fields(solves),
+        title.chars().count() + description.chars().count(),
+    );
+
+    let message = channel_id
+        .send_message(http, |message| {
+            message.embed(|e| {
+                e.title(title);
+                e.description(description);
+
+                for (member, solved) in fields {
+                    e.field(member, solved, false);
+                }
+
+                e
+            })
+        })
+        .await?;
+
+    log::info!("Announced a digest of {} solves", solves.len());
+
+    Ok(message.id)
+}
+
+pub async fn welcome_member(
+    user: &UserData,
+    avatar_url: &str,
+    channel_id: &ChannelId,
+    http: &Http,
+) -> Result<()> {
+    channel_id
+        .send_message(http, |message| {
+            message.embed(|e| {
+                e.title(format!("👋 Welcome to the team, {}!", &user.name));
+                e.thumbnail(avatar_url);
+
+                e
+            })
+        })
+        .await?;
+
+    log::info!("Welcomed new team member {} ({})", user.name, user.id);
+
     Ok(())
 }
+
+pub async fn update_htb_channel_topic_with_stats(
+    stats: &RankStatsData,
+    channel_id: &ChannelId,
+    http: &Http,
+) -> Result<(), Error> {
+    let local_timestamp: DateTime<Local> = Local::now();
+    let timestamp_string = local_timestamp.format("%a %b %e %T").to_string();
+
+    let new_channel_topic = format!(
+        "Team rank {}, Points: {}. Last updated: {}",
+        stats.rank, stats.points, timestamp_string
+    );
+
+    match channel_id.edit(&http, |c| c.topic(new_channel_topic)).await {
+        Ok(_) => Ok(()),
+        Err(why) => Err(eyre!("Error when updating channel topic: {}", why)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_first_bloods_stand_out() {
+        let data = include_str!("../test_resources/recent_activity.json");
+        let solves: Vec<RecentTeamSolve> = serde_json::from_str(data).unwrap();
+
+        let mut solve = SolveToAnnounce {
+            solver: solves[0].user.name.clone(),
+            user_id: solves[0].user.id,
+            solve_type: solves[0].solve_type.clone(),
+            first_blood: false,
+            challenge: Challenge::from(&solves[0]),
+        };
+        let blood_role = Some(RoleId(42));
+
+        assert_eq!(solve_style(&solve, blood_role), (SOLVE_COLOUR, None));
+
+        solve.first_blood = true;
+        assert_eq!(solve_style(&solve, blood_role), (BLOOD_COLOUR, blood_role));
+        assert_eq!(solve_style(&solve, None), (BLOOD_COLOUR, None));
+    }
+
+    #[test]
+    fn test_fit_fields_stays_under_the_embed_limits() {
+        let long_field = |i: usize| (format!("member{i}"), "x".repeat(MAX_FIELD_LENGTH));
+        let length = |fields: &[(String, String)]| {
+            fields
+                .iter()
+                .map(|(name, value)| name.chars().count() + value.chars().count())
+                .sum::<usize>()
+        };
+
+        let few = (0..3).map(long_field).collect::<Vec<_>>();
+        assert_eq!(fit_fields(few.clone(), 100), few);
+
+        let fields = fit_fields((0..10).map(long_field).collect(), 100);
+        assert_eq!(fields.len(), 6);
+        assert_eq!(fields[5].1, "and 5 more members");
+        assert!(100 + length(&fields) <= MAX_EMBED_LENGTH);
+
+        let short_field = |i: usize| (format!("member{i}"), "Machine".to_owned());
+        let fields = fit_fields((0..30).map(short_field).collect(), 100);
+        assert_eq!(fields.len(), MAX_EMBED_FIELDS);
+        assert_eq!(fields[24].1, "and 6 more members");
+    }
+
+    #[test]
+    fn test_digest_fields_group_by_member_and_category() {
+        let data = include_str!("../test_resources/recent_activity.json");
+        let mut solves: Vec<RecentTeamSolve> = serde_json::from_str(data).unwrap();
+
+        let mut user_own = solves[1].clone();
+        user_own.solve_type = "user".to_owned();
+        solves.push(user_own);
+
+        let mut other_member = solves[0].clone();
+        other_member.user.name = "MasterAge".to_owned();
+        solves.push(other_member);
+
+        let fields = digest_fields(&solves);
+
+        assert_eq!(
+            fields,
+            vec![
+                (
+                    "MasterAge".to_owned(),
+                    "**OSINT**: Missing in Action".to_owned()
+                ),
+                (
+                    "wulfgarpro".to_owned(),
+                    "**Machine**: Ophiuchi (root, user)\n**OSINT**: Missing in Action".to_owned()
+                ),
+            ]
+        );
+    }
+}
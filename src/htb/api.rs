@@ -1,205 +1,680 @@
-use std::time::Duration;
-
-use color_eyre::eyre::Error;
-use reqwest::{Client, ClientBuilder};
+use std::{
+    fmt,
+    sync::Arc,
+    time::{Duration, SystemTime},
+};
+
+use async_trait::async_trait;
+use reqwest::{Client, ClientBuilder, Url};
+use serde::de::DeserializeOwned;
 use serde_json::json;
+use totp_rs::{Algorithm, Secret, TOTP};
 
 use crate::{create_reqwest_client, jwt_still_valid};
 
 use super::api_types::*;
+use super::error::{check_status, parse_response, HtbApiError};
+use super::rate_limit::{Priority, RateLimiter};
+use super::retry::RetryCounter;
 
-pub static API_URL: &str = "https://www.hackthebox.eu/api/v4";
+/// Everything the bot needs from HTB, so it can be pointed at something other than the real API.
+#[async_trait]
+pub trait HtbApi: fmt::Debug + Clone + Send + Sync + 'static {
+    fn config(&self) -> &HTBAPIConfig;
 
-pub async fn new_htbapi_instance(config: HTBAPIConfig) -> Result<HTBApiClient, Error> {
-    let login_client = ClientBuilder::new()
-        .timeout(Duration::from_secs(5))
-        .build()
-        .expect("Error when creating login reqwest client");
+    /// The claims of the token currently in use.
+    fn jwt(&self) -> &JWTClaims;
+
+    /// A clone of the client whose requests go through the given lane of the rate limiter.
+    #[must_use]
+    fn with_priority(&self, priority: Priority) -> Self;
+
+    /// Total number of retried requests, shared between all clones of the client.
+    fn retry_count(&self) -> u64;
+
+    async fn list_active_challenges(&self) -> Result<ListActiveChallenges, HtbApiError>;
+
+    async fn list_active_machines(&self) -> Result<ListActiveMachines, HtbApiError>;
+
+    async fn list_team_members(&self) -> Result<Vec<ListTeamMembersData>, HtbApiError>;
+
+    /// The team's activity over the configured window.
+    async fn get_recent_team_activity(&self) -> Result<Vec<RecentTeamSolve>, HtbApiError> {
+        self.get_team_activity(self.config().activity_window_days)
+            .await
+    }
+
+    async fn get_team_activity(
+        &self,
+        n_past_days: u32,
+    ) -> Result<Vec<RecentTeamSolve>, HtbApiError>;
+
+    async fn get_team_statistics(&self) -> Result<GetTeamStatistics, HtbApiError>;
+
+    async fn get_team_rank(&self) -> Result<RankStats, HtbApiError>;
+
+    async fn get_challenge_categories(&self) -> Result<ListChallengeCategories, HtbApiError>;
 
-    let token = login_and_get_token(&config, &login_client).await?;
+    async fn get_challenges(&self) -> Result<ListChallengeCategories, HtbApiError>;
 
-    let client = create_reqwest_client(&token, "Bearer")?;
+    async fn get_user_activity(&self, user_id: i64) -> Result<UserActivity, HtbApiError>;
 
-    let jwt = parse_jwt(&token)?;
+    async fn get_user_overview(&self, user_id: i32) -> Result<UserOverview, HtbApiError>;
+
+    /// Every HTB user whose name matches the query, not just the ones in the team.
+    async fn search_users(&self, query: &str) -> Result<Vec<UserSearchResult>, HtbApiError>;
+
+    /// Renews the token shortly before it expires.
+    async fn handle_token_renewal(&mut self) -> Result<(), HtbApiError>;
+
+    /// Logs in again, regardless of whether the current token is still valid.
+    async fn relogin(&mut self) -> Result<(), HtbApiError>;
+}
+
+pub async fn new_htbapi_instance(config: HTBAPIConfig) -> Result<HTBApiClient, HtbApiError> {
+    let login_client = ClientBuilder::new()
+        .timeout(Duration::from_secs(5))
+        .build()?;
+
+    let rate_limiter = Arc::new(RateLimiter::new(config.requests_per_minute));
+
+    let tokens = match &config.auth {
+        // App tokens are used as they are, there's nothing to log in with.
+        HtbAuth::AppToken(token) => LoginResponseData {
+            access_token: token.clone(),
+            refresh_token: None,
+            is_2fa_enabled: false,
+        },
+        HtbAuth::Password {
+            email,
+            password,
+            totp_secret,
+        } => {
+            rate_limiter.acquire(Priority::High).await;
+            login(
+                &config.api_url,
+                email,
+                password,
+                totp_secret.as_deref(),
+                &login_client,
+            )
+            .await?
+        }
+    };
+
+    let (client, jwt) = client_for_token(&tokens.access_token)?;
 
     Ok(HTBApiClient {
         config,
         client,
         jwt,
+        refresh_token: tokens.refresh_token,
+        retries: RetryCounter::default(),
+        rate_limiter,
+        priority: Priority::High,
     })
 }
 
-async fn login_and_get_token(config: &HTBAPIConfig, client: &Client) -> Result<String, Error> {
-    let url = format!("{}/login", API_URL);
+async fn login(
+    api_url: &str,
+    email: &str,
+    password: &str,
+    totp_secret: Option<&str>,
+    client: &Client,
+) -> Result<LoginResponseData, HtbApiError> {
+    let url = format!("{api_url}/login");
+
+    let login_post_data = json!({"email": email, "password": password, "remember": true});
+
+    let response = client.post(&url).json(&login_post_data).send().await?;
+    let tokens = parse_response::<LoginResponse>(response).await?.message;
+
+    if tokens.is_2fa_enabled {
+        let totp_secret = totp_secret.ok_or(HtbApiError::TwoFactorRequired)?;
+        complete_2fa(api_url, &tokens.access_token, totp_secret, client).await?;
+    }
+
+    Ok(tokens)
+}
+
+/// Sends a one time password for the account, after which its access token can be used.
+async fn complete_2fa(
+    api_url: &str,
+    access_token: &str,
+    totp_secret: &str,
+    client: &Client,
+) -> Result<(), HtbApiError> {
+    let url = format!("{api_url}/2fa/login");
 
-    let login_post_data =
-        json!({"email": config.email, "password": config.password, "remember": true});
+    let one_time_password = totp_code(totp_secret, SystemTime::now())?;
+    let otp_post_data = json!({ "one_time_password": one_time_password });
 
-    let login_response = client
+    let response = client
         .post(&url)
-        .json(&login_post_data)
+        .bearer_auth(access_token)
+        .json(&otp_post_data)
         .send()
-        .await?
-        .json::<LoginResponse>()
         .await?;
 
-    Ok(login_response.message.access_token)
+    check_status(response.status(), response.headers())
+}
+
+/// The 6 digit code an authenticator app would show for the base32 secret at the given time.
+fn totp_code(totp_secret: &str, time: SystemTime) -> Result<String, HtbApiError> {
+    let secret = Secret::Encoded(totp_secret.replace(' ', "").to_uppercase())
+        .to_bytes()
+        .map_err(|why| HtbApiError::InvalidTotpSecret(format!("{why:?}")))?;
+    let seconds = time
+        .duration_since(SystemTime::UNIX_EPOCH)
+        .map_err(|why| HtbApiError::InvalidTotpSecret(why.to_string()))?
+        .as_secs();
+
+    // HTB hands out 80 bit secrets, which are shorter than totp-rs accepts by default.
+    Ok(TOTP::new_unchecked(Algorithm::SHA1, 6, 1, 30, secret).generate(seconds))
+}
+
+async fn refresh_login(
+    config: &HTBAPIConfig,
+    client: &Client,
+    refresh_token: &str,
+) -> Result<LoginResponseData, HtbApiError> {
+    let url = format!("{}/login/refresh", config.api_url);
+
+    let refresh_post_data = json!({ "refresh_token": refresh_token });
+
+    let response = client.post(&url).json(&refresh_post_data).send().await?;
+    let login_response = parse_response::<LoginResponse>(response).await?;
+
+    Ok(login_response.message)
+}
+
+fn client_for_token(token: &str) -> Result<(Client, JWTClaims), HtbApiError> {
+    let jwt = parse_jwt(token)?;
+    let client = create_reqwest_client(token, "Bearer")
+        .map_err(|why| HtbApiError::InvalidToken(why.to_string()))?;
+
+    Ok((client, jwt))
 }
 
 impl HTBApiClient {
-    pub async fn list_active_challenges(&self) -> Result<ListActiveChallenges, Error> {
-        let url = format!("{}/challenge/list", API_URL);
+    async fn get<T: DeserializeOwned>(&self, url: &str) -> Result<T, HtbApiError> {
+        let policy = &self.config.retry;
+        let mut attempt = 0;
+
+        loop {
+            self.rate_limiter.acquire(self.priority).await;
+
+            let result = match self.client.get(url).send().await {
+                Ok(response) => parse_response(response).await,
+                Err(why) => Err(why.into()),
+            };
+
+            match result {
+                Err(why) if why.is_retryable() && attempt < policy.max_retries => {
+                    let Some(delay) = policy.delay(attempt, why.retry_after()) else {
+                        // Leave the backing off to the scheduler, rather than holding up the caller.
+                        log::warn!("Request to {url} failed and HTB asked us to wait: {why}");
+                        return Err(HtbApiError::RateLimited {
+                            retry_after: why.retry_after(),
+                        });
+                    };
+                    attempt += 1;
+                    self.retries.increment();
+
+                    log::warn!(
+                        "Request to {url} failed, retry {attempt} of {} in {delay:?}: {why}",
+                        policy.max_retries
+                    );
+                    tokio::time::sleep(delay).await;
+                }
+                result => return result,
+            }
+        }
+    }
 
-        let active_challenges = self
-            .client
-            .get(&url)
-            .send()
-            .await?
-            .json::<ListActiveChallenges>()
-            .await?;
-        Ok(active_challenges)
+    /// Swaps the refresh token for a new access token.
+    pub async fn refresh(&mut self) -> Result<(), HtbApiError> {
+        let refresh_token = self
+            .refresh_token
+            .clone()
+            .ok_or_else(|| HtbApiError::InvalidToken("HTB never gave us a refresh token".into()))?;
+
+        self.rate_limiter.acquire(self.priority).await;
+        let tokens = refresh_login(&self.config, &self.client, &refresh_token).await?;
+
+        self.use_tokens(tokens)
     }
 
-    pub async fn list_active_machines(&self) -> Result<ListActiveMachines, Error> {
-        let url = format!("{}/machine/list", API_URL);
+    fn use_tokens(&mut self, tokens: LoginResponseData) -> Result<(), HtbApiError> {
+        (self.client, self.jwt) = client_for_token(&tokens.access_token)?;
 
-        let active_machines = self
-            .client
-            .get(&url)
-            .send()
-            .await?
-            .json::<ListActiveMachines>()
-            .await?;
-        Ok(active_machines)
+        // Not every response hands out a new refresh token, in which case the old one still works.
+        if tokens.refresh_token.is_some() {
+            self.refresh_token = tokens.refresh_token;
+        }
+
+        Ok(())
     }
+}
 
-    pub async fn list_team_members(&self) -> Result<Vec<ListTeamMembersData>, Error> {
-        let url = format!("{}/team/members/{}", API_URL, &self.config.team_id);
+#[async_trait]
+impl HtbApi for HTBApiClient {
+    fn config(&self) -> &HTBAPIConfig {
+        &self.config
+    }
 
-        let team_members = self
-            .client
-            .get(&url)
-            .send()
-            .await?
-            .json::<Vec<ListTeamMembersData>>()
-            .await?;
+    fn jwt(&self) -> &JWTClaims {
+        &self.jwt
+    }
 
-        Ok(team_members)
+    fn with_priority(&self, priority: Priority) -> Self {
+        Self {
+            priority,
+            ..self.clone()
+        }
+    }
+
+    fn retry_count(&self) -> u64 {
+        self.retries.get()
+    }
+
+    async fn list_active_challenges(&self) -> Result<ListActiveChallenges, HtbApiError> {
+        let url = format!("{}/challenge/list", self.config.api_url);
+
+        self.get(&url).await
+    }
+
+    async fn list_active_machines(&self) -> Result<ListActiveMachines, HtbApiError> {
+        let url = format!("{}/machine/list", self.config.api_url);
+
+        self.get(&url).await
     }
 
-    pub async fn get_recent_team_activity(&self) -> Result<Vec<RecentTeamSolve>, Error> {
+    async fn list_team_members(&self) -> Result<Vec<ListTeamMembersData>, HtbApiError> {
         let url = format!(
-            "{}/team/activity/{}?n_past_days=90",
-            API_URL, &self.config.team_id
+            "{}/team/members/{}",
+            self.config.api_url, &self.config.team_id
         );
 
-        let team_members = self
-            .client
-            .get(&url)
-            .send()
-            .await?
-            .json::<Vec<RecentTeamSolve>>()
-            .await?;
-
-        Ok(team_members)
+        self.get(&url).await
     }
 
-    pub async fn get_team_statistics(&self) -> Result<GetTeamStatistics, Error> {
-        let url = format!("{}/team/stats/owns/{}", API_URL, &self.config.team_id);
+    async fn get_team_activity(
+        &self,
+        n_past_days: u32,
+    ) -> Result<Vec<RecentTeamSolve>, HtbApiError> {
+        let url = format!(
+            "{}/team/activity/{}?n_past_days={}",
+            self.config.api_url, &self.config.team_id, n_past_days
+        );
 
-        let team_stats = self
-            .client
-            .get(&url)
-            .send()
-            .await?
-            .json::<GetTeamStatistics>()
-            .await?;
+        self.get(&url).await
+    }
+
+    async fn get_team_statistics(&self) -> Result<GetTeamStatistics, HtbApiError> {
+        let url = format!(
+            "{}/team/stats/owns/{}",
+            self.config.api_url, &self.config.team_id
+        );
 
-        Ok(team_stats)
+        self.get(&url).await
     }
 
-    pub async fn get_team_rank(&self) -> Result<RankStats, Error> {
+    async fn get_team_rank(&self) -> Result<RankStats, HtbApiError> {
         let url = format!(
             "{}/rankings/team/ranking_bracket/{}",
-            API_URL, &self.config.team_id
+            self.config.api_url, &self.config.team_id
         );
 
-        let team_rank = self
-            .client
-            .get(&url)
-            .send()
-            .await?
-            .json::<RankStats>()
-            .await?;
+        self.get(&url).await
+    }
+
+    async fn get_challenge_categories(&self) -> Result<ListChallengeCategories, HtbApiError> {
+        let url = format!("{}/challenge/categories/list", self.config.api_url);
 
-        Ok(team_rank)
+        self.get(&url).await
     }
 
-    pub async fn get_challenge_categories(&self) -> Result<ListChallengeCategories, Error> {
-        let url = format!("{}/challenge/categories/list", API_URL);
+    async fn get_challenges(&self) -> Result<ListChallengeCategories, HtbApiError> {
+        let url = format!("{}/challenges?sort_type=asc", self.config.api_url);
 
-        let challenge_categories = self
-            .client
-            .get(&url)
-            .send()
-            .await?
-            .json::<ListChallengeCategories>()
-            .await?;
+        self.get(&url).await
+    }
+
+    async fn get_user_activity(&self, user_id: i64) -> Result<UserActivity, HtbApiError> {
+        let url = format!("{}/user/profile/activity/{}", self.config.api_url, user_id);
 
-        Ok(challenge_categories)
+        self.get(&url).await
     }
 
-    // https://labs.hackthebox.com/api/v4/challenges?sort_type=asc
-    pub async fn get_challenges(&self) -> Result<ListChallengeCategories, Error> {
-        let url = format!("{}/challenges?sort_type=asc", API_URL);
+    async fn get_user_overview(&self, user_id: i32) -> Result<UserOverview, HtbApiError> {
+        let url = format!("{}/user/profile/basic/{}", self.config.api_url, user_id);
 
-        let challenge_categories = self
-            .client
-            .get(&url)
-            .send()
-            .await?
-            .json::<ListChallengeCategories>()
-            .await?;
+        self.get(&url).await
+    }
+
+    async fn search_users(&self, query: &str) -> Result<Vec<UserSearchResult>, HtbApiError> {
+        let url = Url::parse_with_params(
+            &format!("{}/search/fetch", self.config.api_url),
+            [("query", query), ("tags", r#"["users"]"#)],
+        )
+        .map_err(|why| HtbApiError::InvalidUrl(why.to_string()))?;
 
-        Ok(challenge_categories)
+        let results: SearchResults = self.get(url.as_str()).await?;
+
+        Ok(results.users)
     }
 
-    pub async fn get_user_activity(&self, user_id: i64) -> Result<UserActivity, Error> {
-        let url = format!("{}/user/profile/activity/{}", API_URL, user_id);
+    /// Prefers the refresh token over the password, while app tokens are used until they expire.
+    async fn handle_token_renewal(&mut self) -> Result<(), HtbApiError> {
+        if let HtbAuth::AppToken(_) = self.config.auth {
+            return if jwt_still_valid(&self.jwt, Duration::ZERO) {
+                Ok(())
+            } else {
+                Err(HtbApiError::AppTokenExpired)
+            };
+        }
 
-        let users_recent_activity = self
-            .client
-            .get(&url)
-            .send()
-            .await?
-            .json::<UserActivity>()
-            .await?;
+        if jwt_still_valid(&self.jwt, self.config.token_renewal_margin) {
+            return Ok(());
+        }
+
+        if self.refresh_token.is_some() {
+            match self.refresh().await {
+                Ok(()) => return Ok(()),
+                Err(why) => {
+                    log::warn!("Unable to refresh the HTB token, logging in again instead: {why}");
+                }
+            }
+        }
+
+        self.relogin().await
+    }
 
-        Ok(users_recent_activity)
+    /// With an app token this only checks that it hasn't expired.
+    async fn relogin(&mut self) -> Result<(), HtbApiError> {
+        let tokens = match &self.config.auth {
+            HtbAuth::AppToken(_) if !jwt_still_valid(&self.jwt, Duration::ZERO) => {
+                return Err(HtbApiError::AppTokenExpired);
+            }
+            HtbAuth::AppToken(_) => return Ok(()),
+            HtbAuth::Password {
+                email,
+                password,
+                totp_secret,
+            } => {
+                self.rate_limiter.acquire(self.priority).await;
+                login(
+                    &self.config.api_url,
+                    email,
+                    password,
+                    totp_secret.as_deref(),
+                    &self.client,
+                )
+                .await?
+            }
+        };
+
+        self.use_tokens(tokens)
     }
+}
 
-    pub async fn get_user_overview(&self, user_id: i32) -> Result<UserOverview, Error> {
-        let url = format!("{}/user/profile/basic/{}", API_URL, user_id);
+#[cfg(test)]
+mod tests {
+    use std::{fs, path::PathBuf};
 
-        let user_overview = self
-            .client
-            .get(&url)
-            .send()
-            .await?
-            .json::<UserOverview>()
-            .await?;
+    use tokio::{
+        io::{AsyncReadExt, AsyncWriteExt},
+        net::TcpListener,
+    };
+
+    use super::*;
+    use crate::htb::retry::RetryPolicy;
 
-        Ok(user_overview)
+    fn read_file_to_string(filename: &str) -> String {
+        let mut base = PathBuf::from(env!("CARGO_MANIFEST_DIR"));
+        base.push("test_resources");
+        base.push(filename);
+
+        fs::read_to_string(base).unwrap()
     }
 
-    pub async fn handle_token_renewal(&mut self) -> Result<(), Error> {
-        if !jwt_still_valid(&self.jwt) {
-            let token = login_and_get_token(&self.config, &self.client).await?;
+    /// Serves canned JSON bodies for requests whose path, ignoring the query, matches a route,
+    /// returning the api url to point the client at.
+    ///
+    /// When several routes match a path they're served once each in order, apart from the last.
+    async fn mock_htb(mut routes: Vec<(&'static str, u16, String)>) -> String {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let address = listener.local_addr().unwrap();
+
+        tokio::spawn(async move {
+            loop {
+                let (mut socket, _) = listener.accept().await.unwrap();
+
+                let mut buffer = vec![0; 8192];
+                let read = socket.read(&mut buffer).await.unwrap();
+                let request = String::from_utf8_lossy(&buffer[..read]);
+                let path = request
+                    .split_whitespace()
+                    .nth(1)
+                    .and_then(|target| target.split('?').next())
+                    .unwrap_or_default();
+
+                let matching = routes
+                    .iter()
+                    .enumerate()
+                    .filter(|(_, (route, _, _))| path == *route)
+                    .map(|(index, _)| index)
+                    .collect::<Vec<_>>();
+
+                let (status, body) = match matching.as_slice() {
+                    [] => (404, String::new()),
+                    [only] => (routes[*only].1, routes[*only].2.clone()),
+                    [first, ..] => {
+                        let (_, status, body) = routes.remove(*first);
+                        (status, body)
+                    }
+                };
+
+                let response = format!(
+                    "HTTP/1.1 {status} Mock\r\nContent-Type: application/json\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{body}",
+                    body.len()
+                );
+                socket.write_all(response.as_bytes()).await.unwrap();
+            }
+        });
+
+        format!("http://{address}/api/v4")
+    }
 
-            self.jwt = parse_jwt(&token)?;
-            self.client = create_reqwest_client(&token, "Bearer")?;
-        }
+    #[tokio::test]
+    async fn test_client_uses_configured_api_url() {
+        let login = json!({"message": {"access_token": read_file_to_string("jwt.txt")}});
+
+        let api_url = mock_htb(vec![
+            ("/api/v4/login", 200, login.to_string()),
+            (
+                "/api/v4/team/activity/2230",
+                200,
+                read_file_to_string("recent_activity.json"),
+            ),
+            (
+                "/api/v4/search/fetch",
+                200,
+                read_file_to_string("search_users.json"),
+            ),
+        ])
+        .await;
+
+        let htb_api = new_htbapi_instance(test_config(api_url)).await.unwrap();
+        let activity = htb_api.get_recent_team_activity().await.unwrap();
+
+        assert_eq!(activity.len(), 2);
+        assert_eq!(activity[0].name, "Missing in Action");
+
+        let users = htb_api.search_users("Jordyn & co").await.unwrap();
+        assert_eq!(users[0].id, 508037);
+    }
 
-        Ok(())
+    #[tokio::test]
+    async fn test_server_errors_are_retried() {
+        let login = json!({"message": {"access_token": read_file_to_string("jwt.txt")}});
+
+        let api_url = mock_htb(vec![
+            ("/api/v4/login", 200, login.to_string()),
+            (
+                "/api/v4/rankings/team/ranking_bracket/2230",
+                503,
+                String::new(),
+            ),
+            (
+                "/api/v4/rankings/team/ranking_bracket/2230",
+                502,
+                String::new(),
+            ),
+            (
+                "/api/v4/rankings/team/ranking_bracket/2230",
+                200,
+                read_file_to_string("latest_team_stats.json"),
+            ),
+            ("/api/v4/team/members/2230", 404, String::new()),
+        ])
+        .await;
+
+        let htb_api = new_htbapi_instance(test_config(api_url)).await.unwrap();
+
+        let rank = htb_api.get_team_rank().await.unwrap();
+        assert_eq!(rank.data.rank, 381);
+        assert_eq!(htb_api.retry_count(), 2);
+
+        let members = htb_api.list_team_members().await;
+        assert!(matches!(members, Err(HtbApiError::Status { .. })));
+        assert_eq!(htb_api.retry_count(), 2);
+    }
+
+    #[tokio::test]
+    async fn test_token_renewal_prefers_refresh_token() {
+        // The token in jwt.txt expired long ago, so it's always due for renewal.
+        let login = json!({"message": {
+            "access_token": read_file_to_string("jwt.txt"),
+            "refresh_token": "refreshme",
+        }});
+
+        let api_url = mock_htb(vec![
+            ("/api/v4/login", 200, login.to_string()),
+            ("/api/v4/login", 500, String::new()),
+            ("/api/v4/login/refresh", 200, login.to_string()),
+            ("/api/v4/login/refresh", 401, String::new()),
+        ])
+        .await;
+
+        let mut htb_api = new_htbapi_instance(test_config(api_url)).await.unwrap();
+        assert_eq!(htb_api.refresh_token.as_deref(), Some("refreshme"));
+
+        // Logging in with the password is now broken, so only the refresh token can work.
+        htb_api.handle_token_renewal().await.unwrap();
+
+        // Once the refresh token is rejected too, falling back to the password fails.
+        assert!(matches!(
+            htb_api.handle_token_renewal().await,
+            Err(HtbApiError::Server { .. })
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_app_token_skips_login() {
+        // Nothing is mocked for /login, so logging in would fail.
+        let api_url = mock_htb(vec![(
+            "/api/v4/team/activity/2230",
+            200,
+            read_file_to_string("recent_activity.json"),
+        )])
+        .await;
+
+        let config = HTBAPIConfig {
+            auth: HtbAuth::AppToken(read_file_to_string("jwt.txt")),
+            ..test_config(api_url)
+        };
+
+        let mut htb_api = new_htbapi_instance(config).await.unwrap();
+        assert!(htb_api.refresh_token.is_none());
+        assert_eq!(htb_api.get_recent_team_activity().await.unwrap().len(), 2);
+
+        // The token in jwt.txt expired long ago.
+        assert!(matches!(
+            htb_api.handle_token_renewal().await,
+            Err(HtbApiError::AppTokenExpired)
+        ));
+        assert!(matches!(
+            htb_api.relogin().await,
+            Err(HtbApiError::AppTokenExpired)
+        ));
+    }
+
+    #[test]
+    fn test_totp_code() {
+        // The SHA1 test vector from RFC 6238, base32 encoded.
+        let secret = "GEZDGNBVGY3TQOJQGEZDGNBVGY3TQOJQ";
+        let time = SystemTime::UNIX_EPOCH + Duration::from_secs(59);
+
+        assert_eq!(totp_code(secret, time).unwrap(), "287082");
+        assert!(matches!(
+            totp_code("not base32!", time),
+            Err(HtbApiError::InvalidTotpSecret(_))
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_login_completes_2fa() {
+        let login = json!({"message": {
+            "access_token": read_file_to_string("jwt.txt"),
+            "is2FAEnabled": true,
+        }});
+
+        let api_url = mock_htb(vec![
+            ("/api/v4/login", 200, login.to_string()),
+            (
+                "/api/v4/2fa/login",
+                200,
+                json!({"message": "OK"}).to_string(),
+            ),
+        ])
+        .await;
+
+        let with_secret = HTBAPIConfig {
+            auth: HtbAuth::Password {
+                email: "bot@example.com".to_owned(),
+                password: "hunter2".to_owned(),
+                totp_secret: Some("GEZDGNBVGY3TQOJQGEZDGNBVGY3TQOJQ".to_owned()),
+            },
+            ..test_config(api_url.clone())
+        };
+        assert!(new_htbapi_instance(with_secret).await.is_ok());
+
+        assert!(matches!(
+            new_htbapi_instance(test_config(api_url)).await,
+            Err(HtbApiError::TwoFactorRequired)
+        ));
+    }
+
+    fn test_config(api_url: String) -> HTBAPIConfig {
+        HTBAPIConfig {
+            auth: HtbAuth::Password {
+                email: "bot@example.com".to_owned(),
+                password: "hunter2".to_owned(),
+                totp_secret: None,
+            },
+            team_id: 2230,
+            api_url,
+            avatar_url: DEFAULT_AVATAR_URL.to_owned(),
+            profile_url: DEFAULT_PROFILE_URL.to_owned(),
+            retry: RetryPolicy {
+                base_delay: Duration::from_millis(1),
+                ..RetryPolicy::default()
+            },
+            requests_per_minute: DEFAULT_REQUESTS_PER_MINUTE,
+            token_renewal_margin: DEFAULT_TOKEN_RENEWAL_MARGIN,
+            activity_window_days: DEFAULT_ACTIVITY_WINDOW_DAYS,
+        }
     }
 }
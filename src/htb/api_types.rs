@@ -1,26 +1,33 @@
-use base64::decode;
-use color_eyre::eyre::Error;
+use std::{fmt, sync::Arc, time::Duration};
+
+use base64::{decode_config, URL_SAFE_NO_PAD};
 use reqwest::Client;
-use serde::{Deserialize, Serialize};
+use serde::{Deserialize, Deserializer, Serialize};
+
+use super::error::HtbApiError;
+use super::rate_limit::{Priority, RateLimiter};
+use super::retry::{RetryCounter, RetryPolicy};
 
 // All information from https://github.com/Propolisa/htb-api-docs
 
-#[derive(Debug, Deserialize)]
+#[derive(Debug, Deserialize, Clone)]
 pub struct UserActivity {
     pub profile: UserActivityData,
 }
 
-#[derive(Debug, Deserialize)]
+#[derive(Debug, Deserialize, Clone)]
 pub struct UserActivityData {
     pub activity: Vec<ActivityData>,
 }
 
-#[derive(Debug, Deserialize, Eq, PartialEq, Hash)]
+#[derive(Debug, Deserialize, Clone, Eq, PartialEq, Hash)]
 pub struct ActivityData {
     pub date: String,
     pub object_type: String,
     #[serde(rename = "type")]
     pub solve_type: String,
+    #[serde(default)]
+    pub first_blood: bool,
     pub id: i64,
     pub name: String,
     pub points: i64,
@@ -28,30 +35,83 @@ pub struct ActivityData {
     pub challenge_category: Option<String>,
 }
 
-#[derive(Debug, Deserialize)]
+#[derive(Debug, Deserialize, Clone)]
 pub struct UserOverview {
     pub profile: UserOverviewData,
 }
 
-#[derive(Debug, Deserialize)]
+/// What HTB's search finds, only users are asked for.
+#[derive(Debug, Deserialize, Clone, Default)]
+pub struct SearchResults {
+    #[serde(default)]
+    pub users: Vec<UserSearchResult>,
+}
+
+#[derive(Debug, Deserialize, Clone)]
+pub struct UserSearchResult {
+    #[serde(deserialize_with = "number_or_string")]
+    pub id: i64,
+    #[serde(rename = "value")]
+    pub name: String,
+    pub avatar: Option<String>,
+}
+
+/// The search endpoint sends ids as strings, unlike the rest of the API.
+fn number_or_string<'de, D: Deserializer<'de>>(deserializer: D) -> Result<i64, D::Error> {
+    #[derive(Deserialize)]
+    #[serde(untagged)]
+    enum NumberOrString {
+        Number(i64),
+        String(String),
+    }
+
+    match NumberOrString::deserialize(deserializer)? {
+        NumberOrString::Number(number) => Ok(number),
+        NumberOrString::String(string) => string.parse().map_err(serde::de::Error::custom),
+    }
+}
+
+#[derive(Debug, Deserialize, Clone)]
 pub struct UserOverviewData {
     pub id: i64,
     pub name: String,
+    pub avatar: Option<String>,
+    pub points: i64,
+    pub rank: String,
+    /// Position on the global leaderboard, missing for unranked users.
+    pub ranking: Option<i64>,
+    pub current_rank_progress: Option<f64>,
+    pub next_rank: Option<String>,
+    pub system_owns: i64,
+    pub user_owns: i64,
+    pub system_bloods: i64,
+    pub user_bloods: i64,
+    pub respects: i64,
+    pub team: Option<UserTeamData>,
+}
+
+#[derive(Debug, Deserialize, Clone)]
+pub struct UserTeamData {
+    pub id: i64,
+    pub name: String,
+    pub ranking: Option<i64>,
 }
 
-#[derive(Debug, Deserialize)]
+#[derive(Debug, Deserialize, Clone)]
 pub struct GetTeamProfile {
     pub id: i32,
     pub name: String,
     pub points: i32,
 }
 
-#[derive(Debug, Deserialize, Hash, Eq, PartialEq)]
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
 pub struct RecentTeamSolve {
     pub user: UserData,
     pub date: String,
     #[serde(rename = "type")]
     pub solve_type: String,
+    #[serde(default)]
+    pub first_blood: bool,
     pub object_type: String,
     pub id: i64,
     pub name: String,
@@ -60,42 +120,45 @@ pub struct RecentTeamSolve {
     pub machine_avatar: Option<String>,
 }
 
-#[derive(Debug, Deserialize, Hash, Eq, PartialEq)]
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
 pub struct UserData {
     pub id: i64,
     pub name: String,
     pub avatar_thumb: String,
 }
 
-#[derive(Debug, Deserialize)]
+#[derive(Debug, Deserialize, Clone)]
 pub struct GetTeamStatistics {
     pub rank: i32,
     pub user_owns: i32,
     pub system_owns: i32,
 }
 
-#[derive(Debug, Deserialize)]
+#[derive(Debug, Deserialize, Clone)]
 pub struct ListTeamMembers {
     pub data: Vec<ListTeamMembersData>,
 }
 
-#[derive(Debug, Deserialize)]
+#[derive(Debug, Deserialize, Clone)]
 pub struct ListTeamMembersData {
     pub id: i64,
     pub name: String,
-    // pub rank: i32,
-    // pub points: i32,
-    // pub root_owns: i32,
-    // pub user_owns: i32,
-    // pub rank_text: String,
+    // HTB leaves these out or sends null for some members, such as ones who aren't ranked yet.
+    pub rank: Option<i32>,
+    pub points: Option<i32>,
+    pub root_owns: Option<i32>,
+    pub user_owns: Option<i32>,
+    pub root_bloods_count: Option<i32>,
+    pub user_bloods_count: Option<i32>,
+    pub rank_text: Option<String>,
 }
 
-#[derive(Debug, Deserialize)]
+#[derive(Debug, Deserialize, Clone)]
 pub struct ListActiveChallenges {
     pub challenges: Vec<ListActiveChallengesData>,
 }
 
-#[derive(Debug, Deserialize)]
+#[derive(Debug, Deserialize, Clone)]
 pub struct ListActiveChallengesData {
     pub id: i64,
     pub name: String,
@@ -106,12 +169,12 @@ pub struct ListActiveChallengesData {
     pub machine_avatar: Option<String>,
 }
 
-#[derive(Debug, Deserialize)]
+#[derive(Debug, Deserialize, Clone)]
 pub struct ListActiveMachines {
     pub info: Vec<ListActiveMachinesData>,
 }
 
-#[derive(Debug, Deserialize)]
+#[derive(Debug, Deserialize, Clone)]
 pub struct ListActiveMachinesData {
     pub id: i64,
     pub name: String,
@@ -122,43 +185,117 @@ pub struct ListActiveMachinesData {
     pub avatar: String,
 }
 
-#[derive(Debug, Deserialize)]
+#[derive(Debug, Deserialize, Clone)]
 pub struct ListChallengeCategories {
     pub info: Vec<ListChallengeCategoriesData>,
 }
 
-#[derive(Debug, Deserialize)]
+#[derive(Debug, Deserialize, Clone)]
 pub struct ListChallengeCategoriesData {
     pub id: i64,
     pub name: String,
 }
 
-#[derive(Debug, Deserialize)]
+#[derive(Debug, Deserialize, Clone)]
 pub struct LoginResponse {
     pub message: LoginResponseData,
 }
 
-#[derive(Debug, Deserialize)]
+#[derive(Debug, Deserialize, Clone)]
 pub struct LoginResponseData {
     pub access_token: String,
+    pub refresh_token: Option<String>,
+    /// The access token can't be used until a one time password has been sent to `/2fa/login`.
+    #[serde(rename = "is2FAEnabled", default)]
+    pub is_2fa_enabled: bool,
 }
 
-#[derive(Debug, Deserialize)]
+#[derive(Debug, Deserialize, Clone)]
 pub struct RankStats {
     pub data: RankStatsData,
 }
 
-#[derive(Debug, Deserialize)]
+#[derive(Debug, Deserialize, Clone)]
 pub struct RankStatsData {
     pub rank: i32,
     pub points: i32,
+    pub points_for_next_bracket: Option<i32>,
+    pub current_bracket: Option<String>,
+    /// Missing once the team is in the top bracket.
+    pub next_bracket: Option<String>,
 }
 
-#[derive(Debug, Clone)]
+pub static DEFAULT_API_URL: &str = "https://labs.hackthebox.com/api/v4";
+pub static DEFAULT_AVATAR_URL: &str = "https://labs.hackthebox.com";
+pub static DEFAULT_PROFILE_URL: &str = "https://app.hackthebox.com/profile";
+
+/// How the bot authenticates to HTB.
+#[derive(Clone, PartialEq)]
+pub enum HtbAuth {
+    /// Logs in as a regular account, renewing the token with the refresh token HTB hands out.
+    Password {
+        email: String,
+        password: String,
+        /// Base32 secret the one time passwords are generated from, for accounts with 2FA.
+        totp_secret: Option<String>,
+    },
+    /// A long-lived App Token generated from the HTB account settings, which can't be renewed.
+    AppToken(String),
+}
+
+// Keeps the secrets out of the logs when the config is printed.
+impl fmt::Debug for HtbAuth {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Password {
+                email, totp_secret, ..
+            } => f
+                .debug_struct("Password")
+                .field("email", email)
+                .field("password", &"<redacted>")
+                .field("totp_secret", &totp_secret.as_ref().map(|_| "<redacted>"))
+                .finish(),
+            Self::AppToken(_) => f.debug_tuple("AppToken").field(&"<redacted>").finish(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
 pub struct HTBAPIConfig {
-    pub email: String,
-    pub password: String,
+    pub auth: HtbAuth,
     pub team_id: i32,
+    /// Base of every API endpoint, e.g. `https://labs.hackthebox.com/api/v4`.
+    pub api_url: String,
+    /// What the relative avatar paths returned by the API are served from.
+    pub avatar_url: String,
+    /// What a user id is appended to to link to their profile.
+    pub profile_url: String,
+    pub retry: RetryPolicy,
+    /// Shared by every clone of the client, including the ones used by commands.
+    pub requests_per_minute: u32,
+    /// How long before the token expires it is renewed, so requests never race the expiry.
+    pub token_renewal_margin: Duration,
+    /// How many days of team activity are checked for new solves.
+    pub activity_window_days: u32,
+}
+
+pub static DEFAULT_REQUESTS_PER_MINUTE: u32 = 60;
+pub static DEFAULT_TOKEN_RENEWAL_MARGIN: Duration = Duration::from_secs(60 * 60);
+pub static DEFAULT_ACTIVITY_WINDOW_DAYS: u32 = 90;
+
+impl HTBAPIConfig {
+    /// Turns an avatar path from the API, such as `/storage/avatars/abc.png`, into a full url.
+    pub fn avatar_url(&self, avatar: &str) -> String {
+        format!(
+            "{}/{}",
+            self.avatar_url.trim_end_matches('/'),
+            avatar.trim_start_matches('/')
+        )
+    }
+
+    pub fn profile_url(&self, user_id: impl fmt::Display) -> String {
+        format!("{}/{user_id}", self.profile_url.trim_end_matches('/'))
+    }
 }
 
 #[derive(Debug, Clone)]
@@ -166,21 +303,41 @@ pub struct HTBApiClient {
     pub config: HTBAPIConfig,
     pub client: Client,
     pub jwt: JWTClaims,
+    pub refresh_token: Option<String>,
+    pub retries: RetryCounter,
+    pub rate_limiter: Arc<RateLimiter>,
+    /// Which lane of the rate limiter requests made through this clone use.
+    pub priority: Priority,
 }
 
-#[derive(Debug, Serialize, Deserialize, Clone, Copy)]
+/// The claims we care about from the payload of a JWT, all times are unix timestamps.
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
 pub struct JWTClaims {
     pub exp: f64,
+    pub iat: Option<f64>,
+    pub nbf: Option<f64>,
+    /// The id of the HTB user the token belongs to.
+    pub sub: Option<String>,
 }
 
-pub fn parse_jwt(token: &str) -> Result<JWTClaims, Error> {
-    let b64url = token.split('.').collect::<Vec<_>>()[1];
-    let buffer = b64url.replace("/-/g", "+").replace("/_/g", "/");
-    let decoded = decode(buffer)?[..].to_vec();
-    let to_string = String::from_utf8(decoded)?;
-    let jwt_claims: JWTClaims = serde_json::from_str(&to_string)?;
-
-    Ok(jwt_claims)
+/// Reads the claims from a JWT without verifying its signature, which only HTB can do.
+pub fn parse_jwt(token: &str) -> Result<JWTClaims, HtbApiError> {
+    let payload = match token.trim().split('.').collect::<Vec<_>>()[..] {
+        [_, payload, _] => payload,
+        ref segments => {
+            return Err(HtbApiError::InvalidToken(format!(
+                "expected 3 segments, found {}",
+                segments.len()
+            )))
+        }
+    };
+
+    // JWTs are base64url without padding, but tolerate padding in case anything added it.
+    let decoded = decode_config(payload.trim_end_matches('='), URL_SAFE_NO_PAD)
+        .map_err(|why| HtbApiError::InvalidToken(format!("payload isn't base64url: {why}")))?;
+
+    serde_json::from_slice(&decoded)
+        .map_err(|why| HtbApiError::InvalidToken(format!("unreadable claims: {why}")))
 }
 
 #[cfg(test)]
@@ -206,6 +363,7 @@ mod tests {
         assert_eq!(recent_data.len(), 2);
         assert_eq!(recent_data[0].name, "Missing in Action".to_string());
         assert_eq!(recent_data[0].user.name, "wulfgarpro".to_string());
+        assert!(!recent_data[0].first_blood);
 
         assert_eq!(recent_data[1].name, "Ophiuchi".to_string());
         assert_eq!(recent_data[1].object_type, "machine".to_string());
@@ -220,6 +378,30 @@ mod tests {
         let profile = recent_data.profile;
 
         assert_eq!(508037, profile.id);
+        assert_eq!(profile.rank, "Script Kiddie");
+        assert_eq!(profile.ranking, Some(753));
+        assert_eq!(profile.next_rank.as_deref(), Some("Hacker"));
+        assert_eq!(profile.team.unwrap().name, "purple");
+    }
+
+    #[test]
+    fn test_deserialise_search_users() {
+        let data = read_file_to_string("search_users.json");
+
+        let results: SearchResults = serde_json::from_str(&data).unwrap();
+
+        assert_eq!(results.users.len(), 2);
+        assert_eq!(results.users[0].id, 508037);
+        assert_eq!(results.users[0].name, "Jordyn");
+        assert_eq!(results.users[1].avatar, None);
+
+        let numeric: UserSearchResult =
+            serde_json::from_str(r#"{"id": 1, "value": "newbie"}"#).unwrap();
+        assert_eq!(numeric.id, 1);
+        assert!(serde_json::from_str::<SearchResults>(r#"{}"#)
+            .unwrap()
+            .users
+            .is_empty());
     }
 
     #[test]
@@ -244,6 +426,17 @@ mod tests {
         assert_eq!(machines[0].name, "RopeTwo");
     }
 
+    #[test]
+    fn test_deserialise_team_statistics() {
+        let data = read_file_to_string("team_statistics.json");
+
+        let statistics: GetTeamStatistics = serde_json::from_str(&data).unwrap();
+
+        assert_eq!(statistics.rank, 381);
+        assert_eq!(statistics.user_owns, 143);
+        assert_eq!(statistics.system_owns, 131);
+    }
+
     #[test]
     fn test_deserialise_challenge_categories() {
         let data = read_file_to_string("challenge_categories.json");
@@ -263,6 +456,11 @@ mod tests {
         let login_response: LoginResponse = serde_json::from_str(&data).unwrap();
 
         assert_eq!(login_response.message.access_token, "abcd");
+        assert_eq!(
+            login_response.message.refresh_token.as_deref(),
+            Some("refreshme")
+        );
+        assert!(!login_response.message.is_2fa_enabled);
     }
 
     #[test]
@@ -276,6 +474,7 @@ mod tests {
         assert_eq!(activity.id, 344);
         assert_eq!(activity.solve_type, "root");
         assert_eq!(activity.name, "Love");
+        assert!(!activity.first_blood);
     }
 
     #[test]
@@ -288,6 +487,17 @@ mod tests {
 
         assert_eq!(member.id, 66487);
         assert_eq!(member.name, "wulfgarpro");
+        assert_eq!(member.points, Some(58));
+        assert_eq!(member.root_owns, Some(64));
+        assert_eq!(member.user_owns, Some(66));
+        assert_eq!(member.rank_text.as_deref(), Some("Pro Hacker"));
+
+        let unranked: ListTeamMembersData =
+            serde_json::from_str(r#"{"id": 1, "name": "newbie", "rank": null, "points": null}"#)
+                .unwrap();
+        assert_eq!(unranked.rank, None);
+        assert_eq!(unranked.points, None);
+        assert_eq!(unranked.rank_text, None);
     }
 
     #[test]
@@ -298,14 +508,77 @@ mod tests {
 
         assert_eq!(team_stats.data.rank, 381);
         assert_eq!(team_stats.data.points, 101);
+        assert_eq!(team_stats.data.points_for_next_bracket, Some(108));
+        assert_eq!(team_stats.data.current_bracket.as_deref(), Some("301-400"));
+        assert_eq!(team_stats.data.next_bracket.as_deref(), Some("251-300"));
+    }
+
+    #[test]
+    fn test_avatar_url() {
+        let config = HTBAPIConfig {
+            auth: HtbAuth::AppToken(String::new()),
+            team_id: 2230,
+            api_url: DEFAULT_API_URL.to_owned(),
+            avatar_url: "https://labs.hackthebox.com/".to_owned(),
+            profile_url: "https://app.hackthebox.com/profile/".to_owned(),
+            retry: RetryPolicy::default(),
+            requests_per_minute: DEFAULT_REQUESTS_PER_MINUTE,
+            token_renewal_margin: DEFAULT_TOKEN_RENEWAL_MARGIN,
+            activity_window_days: DEFAULT_ACTIVITY_WINDOW_DAYS,
+        };
+
+        assert_eq!(
+            config.avatar_url("/storage/avatars/abc_thumb.png"),
+            "https://labs.hackthebox.com/storage/avatars/abc_thumb.png"
+        );
+        assert_eq!(
+            config.profile_url(66487),
+            "https://app.hackthebox.com/profile/66487"
+        );
     }
 
     #[test]
     fn test_deserialise_jwt() {
         let token = read_file_to_string("jwt.txt");
 
-        let claims = parse_jwt(&token);
+        let claims = parse_jwt(&token).unwrap();
+
+        assert_eq!(
+            claims,
+            JWTClaims {
+                exp: 1_627_734_703.0,
+                iat: Some(1_625_142_703.0),
+                nbf: Some(1_625_142_703.0),
+                sub: Some("508037".to_owned()),
+            }
+        );
+    }
 
-        assert!(claims.is_ok());
+    #[test]
+    fn test_deserialise_jwt_with_url_safe_characters() {
+        // The payload encodes `"sub": "~~~"` as `n5-fi`, and `"sub": "ÿÿ"` as `IsO_w78`.
+        let tilde = parse_jwt("e30.eyJleHAiOiAxNjI3NzM0NzAzLCAic3ViIjogIn5-fiJ9.sig").unwrap();
+        let accented = parse_jwt("e30.eyJleHAiOiAxNjI3NzM0NzAzLCAic3ViIjogIsO_w78ifQ.sig").unwrap();
+
+        assert_eq!(tilde.sub.as_deref(), Some("~~~"));
+        assert_eq!(accented.sub.as_deref(), Some("ÿÿ"));
+    }
+
+    #[test]
+    fn test_malformed_jwts_are_errors() {
+        for token in [
+            "",
+            "not a jwt",
+            "e30.e30",
+            "e30.!!!.sig",
+            "e30.bm90IGpzb24.sig",
+            // Valid json, but without an `exp`.
+            "e30.e30.sig",
+        ] {
+            assert!(
+                matches!(parse_jwt(token), Err(HtbApiError::InvalidToken(_))),
+                "{token:?} should have been rejected"
+            );
+        }
     }
 }
@@ -0,0 +1,180 @@
+use std::time::Duration;
+
+use reqwest::{
+    header::{HeaderMap, RETRY_AFTER},
+    Response, StatusCode,
+};
+use serde::de::DeserializeOwned;
+
+/// How much of an undecodable body is kept around for debugging.
+const SNIPPET_LENGTH: usize = 200;
+
+/// Everything that can go wrong talking to HTB, split up by what the caller should do about it.
+#[derive(Debug, thiserror::Error)]
+pub enum HtbApiError {
+    /// The token has expired or been revoked, logging in again should fix it.
+    #[error("HTB rejected our credentials ({status})")]
+    Unauthorized { status: StatusCode },
+    #[error("HTB is rate limiting us, retry after {retry_after:?}")]
+    RateLimited { retry_after: Option<Duration> },
+    #[error("HTB returned a server error ({status})")]
+    Server {
+        status: StatusCode,
+        retry_after: Option<Duration>,
+    },
+    /// Any other unsuccessful status, such as a 404 for an unknown user.
+    #[error("HTB returned an unexpected status ({status})")]
+    Status { status: StatusCode },
+    #[error("Request to HTB timed out")]
+    Timeout,
+    /// The response didn't match what we expected, which usually means HTB changed their API.
+    ///
+    /// The body is left out of the message as it ends up in discord, it's only logged at debug.
+    #[error("Unable to decode response from HTB: {source}")]
+    Decode {
+        source: serde_json::Error,
+        snippet: String,
+    },
+    #[error("Network error talking to HTB: {0}")]
+    Network(reqwest::Error),
+    #[error("Unable to build a url for HTB: {0}")]
+    InvalidUrl(String),
+    #[error("Unable to use the token HTB gave us: {0}")]
+    InvalidToken(String),
+    /// App tokens can't be renewed, someone has to generate a new one.
+    #[error("The HTB app token has expired, generate a new one from your HTB account settings")]
+    AppTokenExpired,
+    #[error("The HTB account has 2FA enabled but no TOTP secret is configured")]
+    TwoFactorRequired,
+    #[error("Unable to generate a one time password: {0}")]
+    InvalidTotpSecret(String),
+}
+
+impl HtbApiError {
+    /// Whether the same request might succeed if it's sent again.
+    pub fn is_retryable(&self) -> bool {
+        matches!(
+            self,
+            Self::RateLimited { .. } | Self::Server { .. } | Self::Timeout | Self::Network(_)
+        )
+    }
+
+    pub fn retry_after(&self) -> Option<Duration> {
+        match self {
+            Self::RateLimited { retry_after } | Self::Server { retry_after, .. } => *retry_after,
+            _ => None,
+        }
+    }
+}
+
+impl From<reqwest::Error> for HtbApiError {
+    fn from(why: reqwest::Error) -> Self {
+        if why.is_timeout() {
+            Self::Timeout
+        } else {
+            Self::Network(why)
+        }
+    }
+}
+
+/// Checks the status of a response and decodes its body.
+pub async fn parse_response<T: DeserializeOwned>(response: Response) -> Result<T, HtbApiError> {
+    check_status(response.status(), response.headers())?;
+
+    let body = response.text().await?;
+
+    decode(&body)
+}
+
+pub fn check_status(status: StatusCode, headers: &HeaderMap) -> Result<(), HtbApiError> {
+    match status {
+        status if status.is_success() => Ok(()),
+        StatusCode::UNAUTHORIZED | StatusCode::FORBIDDEN => {
+            Err(HtbApiError::Unauthorized { status })
+        }
+        StatusCode::TOO_MANY_REQUESTS => Err(HtbApiError::RateLimited {
+            retry_after: retry_after(headers),
+        }),
+        status if status.is_server_error() => Err(HtbApiError::Server {
+            status,
+            retry_after: retry_after(headers),
+        }),
+        status => Err(HtbApiError::Status { status }),
+    }
+}
+
+pub fn decode<T: DeserializeOwned>(body: &str) -> Result<T, HtbApiError> {
+    serde_json::from_str(body).map_err(|source| {
+        let snippet: String = body.chars().take(SNIPPET_LENGTH).collect();
+        log::debug!("Unable to decode response from HTB: {source}, body: {snippet}");
+
+        HtbApiError::Decode { source, snippet }
+    })
+}
+
+/// Only the delay-seconds form of `Retry-After` is supported, HTB doesn't send dates.
+fn retry_after(headers: &HeaderMap) -> Option<Duration> {
+    headers
+        .get(RETRY_AFTER)?
+        .to_str()
+        .ok()?
+        .trim()
+        .parse::<u64>()
+        .ok()
+        .map(Duration::from_secs)
+}
+
+#[cfg(test)]
+mod tests {
+    use reqwest::header::HeaderValue;
+
+    use super::*;
+    use crate::htb::api_types::RankStats;
+
+    #[test]
+    fn test_check_status() {
+        let mut headers = HeaderMap::new();
+        assert!(check_status(StatusCode::OK, &headers).is_ok());
+
+        assert!(matches!(
+            check_status(StatusCode::UNAUTHORIZED, &headers),
+            Err(HtbApiError::Unauthorized { .. })
+        ));
+        assert!(matches!(
+            check_status(StatusCode::NOT_FOUND, &headers),
+            Err(HtbApiError::Status { .. })
+        ));
+
+        headers.insert(RETRY_AFTER, HeaderValue::from_static("30"));
+        assert!(matches!(
+            check_status(StatusCode::TOO_MANY_REQUESTS, &headers),
+            Err(HtbApiError::RateLimited {
+                retry_after: Some(retry_after)
+            }) if retry_after == Duration::from_secs(30)
+        ));
+        assert!(matches!(
+            check_status(StatusCode::SERVICE_UNAVAILABLE, &headers),
+            Err(HtbApiError::Server {
+                retry_after: Some(_),
+                ..
+            })
+        ));
+    }
+
+    #[test]
+    fn test_decode_keeps_snippet_of_body() {
+        let body = format!("<html>{}</html>", "a".repeat(500));
+
+        let Err(why) = decode::<RankStats>(&body) else {
+            panic!("Expected a decode error");
+        };
+        assert!(!why.to_string().contains("<html>"));
+
+        let HtbApiError::Decode { snippet, .. } = why else {
+            panic!("Expected a decode error");
+        };
+
+        assert_eq!(snippet.len(), SNIPPET_LENGTH);
+        assert!(snippet.starts_with("<html>"));
+    }
+}
@@ -0,0 +1,221 @@
+use std::sync::{
+    atomic::{AtomicU64, Ordering},
+    Arc,
+};
+
+use async_trait::async_trait;
+use chrono::{Duration, Utc};
+use reqwest::{header::HeaderMap, StatusCode};
+use serde::de::DeserializeOwned;
+
+use super::api::HtbApi;
+use super::api_types::*;
+use super::error::{check_status, HtbApiError};
+use super::rate_limit::Priority;
+use super::retry::RetryPolicy;
+
+fn fixture<T: DeserializeOwned>(json: &str) -> T {
+    serde_json::from_str(json).expect("The fixtures in test_resources should always deserialise")
+}
+
+/// An in-memory stand in for HTB, answering every request from the JSON fixtures in `test_resources`.
+///
+/// Every response is a public field, so tests can change what HTB says before running the bot against it.
+#[derive(Debug, Clone)]
+pub struct FakeHtbApi {
+    pub config: HTBAPIConfig,
+    pub jwt: JWTClaims,
+    pub active_challenges: ListActiveChallenges,
+    pub active_machines: ListActiveMachines,
+    pub team_members: Vec<ListTeamMembersData>,
+    pub team_activity: Vec<RecentTeamSolve>,
+    pub team_statistics: GetTeamStatistics,
+    pub team_rank: RankStats,
+    pub challenge_categories: ListChallengeCategories,
+    pub user_activity: UserActivity,
+    /// Only served for the user it belongs to, anyone else is a 404.
+    pub user_overview: UserOverview,
+    /// Searches only return the users here whose name contains the query.
+    pub user_search: SearchResults,
+    /// When set, every request fails as if HTB had responded with this status.
+    pub failing_status: Option<StatusCode>,
+    /// How many times `relogin` has been called, shared between all clones.
+    pub relogins: Arc<AtomicU64>,
+}
+
+impl FakeHtbApi {
+    pub fn new() -> Self {
+        Self {
+            config: HTBAPIConfig {
+                auth: HtbAuth::AppToken("fake".to_owned()),
+                team_id: 2230,
+                api_url: DEFAULT_API_URL.to_owned(),
+                avatar_url: DEFAULT_AVATAR_URL.to_owned(),
+                profile_url: DEFAULT_PROFILE_URL.to_owned(),
+                retry: RetryPolicy::default(),
+                requests_per_minute: DEFAULT_REQUESTS_PER_MINUTE,
+                token_renewal_margin: DEFAULT_TOKEN_RENEWAL_MARGIN,
+                activity_window_days: DEFAULT_ACTIVITY_WINDOW_DAYS,
+            },
+            jwt: JWTClaims {
+                exp: (Utc::now() + Duration::days(1)).timestamp() as f64,
+                iat: None,
+                nbf: None,
+                sub: Some("508037".to_owned()),
+            },
+            active_challenges: fixture(include_str!("../../test_resources/list_challenges.json")),
+            active_machines: fixture(include_str!("../../test_resources/list_machines.json")),
+            team_members: fixture(include_str!("../../test_resources/get_team_members.json")),
+            team_activity: fixture(include_str!("../../test_resources/recent_activity.json")),
+            team_statistics: fixture(include_str!("../../test_resources/team_statistics.json")),
+            team_rank: fixture(include_str!("../../test_resources/latest_team_stats.json")),
+            challenge_categories: fixture(include_str!(
+                "../../test_resources/challenge_categories.json"
+            )),
+            user_activity: fixture(include_str!("../../test_resources/get_user_activity.json")),
+            user_overview: fixture(include_str!("../../test_resources/user_overview.json")),
+            user_search: fixture(include_str!("../../test_resources/search_users.json")),
+            failing_status: None,
+            relogins: Arc::new(AtomicU64::new(0)),
+        }
+    }
+
+    fn respond<T: Clone>(&self, response: &T) -> Result<T, HtbApiError> {
+        if let Some(status) = self.failing_status {
+            check_status(status, &HeaderMap::new())?;
+        }
+
+        Ok(response.clone())
+    }
+}
+
+impl Default for FakeHtbApi {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[async_trait]
+impl HtbApi for FakeHtbApi {
+    fn config(&self) -> &HTBAPIConfig {
+        &self.config
+    }
+
+    fn jwt(&self) -> &JWTClaims {
+        &self.jwt
+    }
+
+    fn with_priority(&self, _priority: Priority) -> Self {
+        self.clone()
+    }
+
+    fn retry_count(&self) -> u64 {
+        0
+    }
+
+    async fn list_active_challenges(&self) -> Result<ListActiveChallenges, HtbApiError> {
+        self.respond(&self.active_challenges)
+    }
+
+    async fn list_active_machines(&self) -> Result<ListActiveMachines, HtbApiError> {
+        self.respond(&self.active_machines)
+    }
+
+    async fn list_team_members(&self) -> Result<Vec<ListTeamMembersData>, HtbApiError> {
+        self.respond(&self.team_members)
+    }
+
+    async fn get_team_activity(
+        &self,
+        _n_past_days: u32,
+    ) -> Result<Vec<RecentTeamSolve>, HtbApiError> {
+        self.respond(&self.team_activity)
+    }
+
+    async fn get_team_statistics(&self) -> Result<GetTeamStatistics, HtbApiError> {
+        self.respond(&self.team_statistics)
+    }
+
+    async fn get_team_rank(&self) -> Result<RankStats, HtbApiError> {
+        self.respond(&self.team_rank)
+    }
+
+    async fn get_challenge_categories(&self) -> Result<ListChallengeCategories, HtbApiError> {
+        self.respond(&self.challenge_categories)
+    }
+
+    async fn get_challenges(&self) -> Result<ListChallengeCategories, HtbApiError> {
+        self.respond(&self.challenge_categories)
+    }
+
+    async fn get_user_activity(&self, _user_id: i64) -> Result<UserActivity, HtbApiError> {
+        self.respond(&self.user_activity)
+    }
+
+    async fn get_user_overview(&self, user_id: i32) -> Result<UserOverview, HtbApiError> {
+        if i64::from(user_id) != self.user_overview.profile.id {
+            return Err(HtbApiError::Status {
+                status: StatusCode::NOT_FOUND,
+            });
+        }
+
+        self.respond(&self.user_overview)
+    }
+
+    async fn search_users(&self, query: &str) -> Result<Vec<UserSearchResult>, HtbApiError> {
+        let query = query.to_lowercase();
+        let users = self
+            .user_search
+            .users
+            .iter()
+            .filter(|user| user.name.to_lowercase().contains(&query))
+            .cloned()
+            .collect();
+
+        self.respond(&users)
+    }
+
+    async fn handle_token_renewal(&mut self) -> Result<(), HtbApiError> {
+        self.respond(&())
+    }
+
+    async fn relogin(&mut self) -> Result<(), HtbApiError> {
+        self.relogins.fetch_add(1, Ordering::Relaxed);
+
+        self.respond(&())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_serves_fixtures() {
+        let fake = FakeHtbApi::new();
+
+        assert_eq!(fake.get_recent_team_activity().await.unwrap().len(), 2);
+        assert_eq!(fake.get_team_rank().await.unwrap().data.rank, 381);
+        assert_eq!(fake.get_team_statistics().await.unwrap().system_owns, 131);
+        assert!(fake.get_user_overview(508_037).await.is_ok());
+        assert!(matches!(
+            fake.get_user_overview(1).await,
+            Err(HtbApiError::Status { .. })
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_failing_status() {
+        let mut fake = FakeHtbApi {
+            failing_status: Some(StatusCode::UNAUTHORIZED),
+            ..FakeHtbApi::new()
+        };
+
+        assert!(matches!(
+            fake.list_team_members().await,
+            Err(HtbApiError::Unauthorized { .. })
+        ));
+        assert!(fake.relogin().await.is_err());
+        assert_eq!(fake.relogins.load(Ordering::Relaxed), 1);
+    }
+}
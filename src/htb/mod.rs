@@ -1,2 +1,7 @@
 pub mod api;
 pub mod api_types;
+pub mod error;
+#[cfg(any(test, feature = "fake"))]
+pub mod fake;
+pub mod rate_limit;
+pub mod retry;
@@ -0,0 +1,121 @@
+use std::{
+    sync::Mutex,
+    time::{Duration, Instant},
+};
+
+/// Up to this many seconds' worth of requests can be sent in a burst.
+const BURST_SECONDS: f64 = 10.0;
+/// Share of the bucket only high priority requests can use.
+const RESERVED_SHARE: f64 = 0.25;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Priority {
+    /// Background work the bot can't do without, such as polling for solves.
+    #[default]
+    High,
+    /// Lookups made on behalf of commands, which can wait their turn.
+    Low,
+}
+
+/// Token bucket limiting how fast requests are sent to HTB, shared by every clone of the client.
+///
+/// Low priority requests can't dip into the reserved part of the bucket, so a flood of
+/// command lookups never starves the solve poller.
+#[derive(Debug)]
+pub struct RateLimiter {
+    capacity: f64,
+    reserved: f64,
+    tokens_per_second: f64,
+    bucket: Mutex<Bucket>,
+}
+
+#[derive(Debug)]
+struct Bucket {
+    tokens: f64,
+    last_refill: Instant,
+}
+
+impl RateLimiter {
+    pub fn new(requests_per_minute: u32) -> Self {
+        let tokens_per_second = f64::from(requests_per_minute.max(1)) / 60.0;
+        let capacity = (tokens_per_second * BURST_SECONDS).max(2.0);
+
+        Self {
+            capacity,
+            reserved: capacity * RESERVED_SHARE,
+            tokens_per_second,
+            bucket: Mutex::new(Bucket {
+                tokens: capacity,
+                last_refill: Instant::now(),
+            }),
+        }
+    }
+
+    /// Waits until a request with the given priority is allowed to be sent.
+    pub async fn acquire(&self, priority: Priority) {
+        while let Err(wait) = self.try_acquire(priority) {
+            tokio::time::sleep(wait).await;
+        }
+    }
+
+    /// Takes a token if one is available, otherwise returns roughly how long until one will be.
+    pub fn try_acquire(&self, priority: Priority) -> Result<(), Duration> {
+        let mut bucket = self
+            .bucket
+            .lock()
+            .unwrap_or_else(std::sync::PoisonError::into_inner);
+
+        let now = Instant::now();
+        let elapsed = now.duration_since(bucket.last_refill).as_secs_f64();
+        bucket.tokens = (bucket.tokens + elapsed * self.tokens_per_second).min(self.capacity);
+        bucket.last_refill = now;
+
+        let needed = match priority {
+            Priority::High => 1.0,
+            Priority::Low => 1.0 + self.reserved,
+        };
+
+        if bucket.tokens >= needed {
+            bucket.tokens -= 1.0;
+            Ok(())
+        } else {
+            Err(Duration::from_secs_f64(
+                (needed - bucket.tokens) / self.tokens_per_second,
+            ))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_low_priority_leaves_reserve_for_high_priority() {
+        // 60 requests a minute gives a bucket of 10, with 2.5 reserved.
+        let limiter = RateLimiter::new(60);
+
+        let mut low_priority_sent = 0;
+        while limiter.try_acquire(Priority::Low).is_ok() {
+            low_priority_sent += 1;
+        }
+
+        assert_eq!(low_priority_sent, 7);
+
+        for _ in 0..3 {
+            assert!(limiter.try_acquire(Priority::High).is_ok());
+        }
+        assert!(limiter.try_acquire(Priority::High).is_err());
+    }
+
+    #[test]
+    fn test_wait_reflects_refill_rate() {
+        let limiter = RateLimiter::new(6);
+
+        while limiter.try_acquire(Priority::High).is_ok() {}
+
+        let wait = limiter.try_acquire(Priority::High).unwrap_err();
+        assert!(wait <= Duration::from_secs(10));
+        assert!(wait > Duration::from_secs(9));
+    }
+}
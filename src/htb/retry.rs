@@ -0,0 +1,94 @@
+use std::{
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc,
+    },
+    time::Duration,
+};
+
+use rand::Rng;
+
+/// How failed GET requests are retried, POSTs such as logging in are never retried.
+#[derive(Debug, Clone, PartialEq)]
+pub struct RetryPolicy {
+    pub max_retries: u32,
+    pub base_delay: Duration,
+    pub max_delay: Duration,
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        Self {
+            max_retries: 3,
+            base_delay: Duration::from_millis(500),
+            max_delay: Duration::from_secs(30),
+        }
+    }
+}
+
+impl RetryPolicy {
+    /// How long to wait before the given retry, starting from 0.
+    ///
+    /// A `Retry-After` from HTB is honoured as long as it isn't longer than `max_delay`, `None`
+    /// means it is and the request should give up rather than sit waiting. Otherwise the delay
+    /// doubles on every attempt and is jittered between half and all of that, so clones of the
+    /// client don't retry in lockstep.
+    pub fn delay(&self, attempt: u32, retry_after: Option<Duration>) -> Option<Duration> {
+        if let Some(retry_after) = retry_after {
+            return (retry_after <= self.max_delay).then_some(retry_after);
+        }
+
+        let backoff = self
+            .base_delay
+            .saturating_mul(2_u32.saturating_pow(attempt))
+            .min(self.max_delay);
+
+        Some(backoff.mul_f64(rand::thread_rng().gen_range(0.5..=1.0)))
+    }
+}
+
+/// Total number of retried requests, shared between all clones of the client.
+#[derive(Debug, Clone, Default)]
+pub struct RetryCounter(Arc<AtomicU64>);
+
+impl RetryCounter {
+    pub fn increment(&self) {
+        self.0.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn get(&self) -> u64 {
+        self.0.load(Ordering::Relaxed)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_retry_delay() {
+        let policy = RetryPolicy {
+            max_retries: 5,
+            base_delay: Duration::from_secs(1),
+            max_delay: Duration::from_secs(10),
+        };
+
+        let first = policy.delay(0, None).unwrap();
+        assert!(first >= Duration::from_millis(500) && first <= Duration::from_secs(1));
+
+        let third = policy.delay(2, None).unwrap();
+        assert!(third >= Duration::from_secs(2) && third <= Duration::from_secs(4));
+
+        assert!(policy.delay(10, None).unwrap() <= Duration::from_secs(10));
+        assert_eq!(
+            policy.delay(0, Some(Duration::from_secs(5))),
+            Some(Duration::from_secs(5))
+        );
+
+        // Waiting a day in the middle of a request isn't worth it.
+        assert_eq!(
+            policy.delay(0, Some(Duration::from_secs(24 * 60 * 60))),
+            None
+        );
+    }
+}
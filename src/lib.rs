@@ -1,21 +1,52 @@
-use std::time::Duration;
+use std::{sync::Arc, time::Duration};
 
-use chrono::{DateTime, Local};
-use color_eyre::eyre::{eyre, Error, Result};
+use chrono::Local;
+use color_eyre::eyre::{Error, Result};
 use dashmap::{DashMap, DashSet};
-use htb::api_types::*;
+use htb::{api::HtbApi, api_types::*};
+use notifier::Notifier;
 use once_cell::sync::Lazy;
 use reqwest::{
     header::{HeaderMap, HeaderValue},
     Client, ClientBuilder,
 };
-use serenity::{http::Http, model::id::ChannelId};
+use serde::{Deserialize, Serialize};
+use store::{SavedMembers, SolveStore};
 
+pub mod config;
 pub mod htb;
+pub mod notifier;
+pub mod polling;
+pub mod scheduler;
+pub mod store;
+
+pub static SOLVE_CACHE: Lazy<DashMap<i64, DashSet<SolveKey>>> = Lazy::new(DashMap::new);
+pub static TEAM_MEMBERS: Lazy<DashSet<i64>> = Lazy::new(DashSet::new);
+/// Team members who haven't been welcomed yet, a subset of `TEAM_MEMBERS`.
+pub static UNWELCOMED_MEMBERS: Lazy<DashSet<i64>> = Lazy::new(DashSet::new);
+
+/// Identifies a solve independently of how HTB happens to render it, so that a changed
+/// avatar, date format or points rescale doesn't make an old solve look new.
+#[derive(Debug, Clone, Hash, Eq, PartialEq, Serialize, Deserialize)]
+pub struct SolveKey {
+    pub user_id: i64,
+    pub object_type: String,
+    pub object_id: i64,
+    pub solve_type: String,
+}
 
-pub static SOLVE_CACHE: Lazy<DashMap<i64, DashSet<RecentTeamSolve>>> = Lazy::new(DashMap::new);
+impl From<&RecentTeamSolve> for SolveKey {
+    fn from(solve: &RecentTeamSolve) -> Self {
+        Self {
+            user_id: solve.user.id,
+            object_type: solve.object_type.to_lowercase(),
+            object_id: solve.id,
+            solve_type: solve.solve_type.to_lowercase(),
+        }
+    }
+}
 
-#[derive(Debug)]
+#[derive(Debug, Clone, PartialEq)]
 pub struct Challenge {
     pub name: String,
     pub points: i64,
@@ -24,61 +55,149 @@ pub struct Challenge {
     pub challenge_category: Option<String>,
 }
 
+impl From<&RecentTeamSolve> for Challenge {
+    fn from(solve: &RecentTeamSolve) -> Self {
+        Self {
+            name: solve.name.clone(),
+            points: solve.points,
+            challenge_type: solve.object_type.clone(),
+            machine_avatar: solve.machine_avatar.clone(),
+            challenge_category: solve.challenge_category.clone(),
+        }
+    }
+}
+
+pub static DEFAULT_CATCH_UP_THRESHOLD: usize = 5;
+
 #[derive(Debug)]
-pub struct ScheduleRunnerData {
-    pub htb_api: HTBApiClient,
-    pub http: Http,
-    pub channel_id: ChannelId,
+pub struct ScheduleRunnerData<A = HTBApiClient> {
+    pub htb_api: A,
+    pub notifier: Arc<dyn Notifier>,
+    pub store: SolveStore,
+    /// Once more than this many solves are waiting to be announced they're posted as a single digest.
+    pub catch_up_threshold: usize,
 }
 
-pub async fn load_solves_to_cache(htb_api: &HTBApiClient) -> Result<()> {
+/// Fills `SOLVE_CACHE` with every solve that has already been seen.
+///
+/// When the store has been written before, it is the source of truth, so anything solved
+/// while the bot was offline is still treated as new. On the very first run the store is
+/// seeded with the current team activity instead, to avoid announcing months of history.
+pub async fn load_solves_to_cache(htb_api: &impl HtbApi, store: &SolveStore) -> Result<()> {
+    if store.exists() {
+        let stored_solves = store.load()?;
+        log::info!(
+            "Loaded {} solves from {}",
+            stored_solves.len(),
+            store.path().display()
+        );
+
+        for stored in stored_solves {
+            cache_solve(stored.key);
+        }
+
+        return Ok(());
+    }
+
+    log::info!(
+        "No solve store found at {}, seeding it from recent team activity...",
+        store.path().display()
+    );
+
     let team_solves = htb_api.get_recent_team_activity().await?;
+    store.seed(&team_solves)?;
+
+    for solve in &team_solves {
+        cache_solve(SolveKey::from(solve));
+    }
+
+    Ok(())
+}
 
-    for solve in team_solves {
-        match SOLVE_CACHE.get_mut(&solve.user.id) {
-            Some(solves) => {
-                solves.insert(solve);
+/// Fills `TEAM_MEMBERS` and `UNWELCOMED_MEMBERS` from the store, so anyone who joined while
+/// the bot was offline is still found by the next refresh and welcomed.
+///
+/// HTB is only asked when the members have never been saved.
+pub async fn load_team_members(htb_api: &impl HtbApi, store: &SolveStore) -> Result<()> {
+    match store.load_members()? {
+        Some(saved) => {
+            log::info!(
+                "Loaded {} team members from {}",
+                saved.members.len(),
+                store.members_path().display()
+            );
+
+            for id in saved.members {
+                TEAM_MEMBERS.insert(id);
             }
-            None => {
-                let solves = DashSet::new();
-                let user_id = solve.user.id;
-                solves.insert(solve);
-                SOLVE_CACHE.insert(user_id, solves);
+
+            for id in saved.unwelcomed {
+                UNWELCOMED_MEMBERS.insert(id);
             }
-        };
+        }
+        None => {
+            refresh_team_members(htb_api, store).await?;
+        }
     }
 
     Ok(())
 }
 
-pub async fn update_htb_channel_topic_with_stats(
-    stats: &RankStatsData,
-    channel_id: &ChannelId,
-    http: &Http,
-) -> Result<(), Error> {
-    let local_timestamp: DateTime<Local> = Local::now();
-    let timestamp_string = local_timestamp.format("%a %b %e %T").to_string();
-
-    let new_channel_topic = format!(
-        "Team rank {}, Points: {}. Last updated: {}",
-        stats.rank, stats.points, timestamp_string
-    );
+fn save_team_members(store: &SolveStore) {
+    let saved = SavedMembers {
+        members: TEAM_MEMBERS.iter().map(|id| *id).collect(),
+        unwelcomed: UNWELCOMED_MEMBERS.iter().map(|id| *id).collect(),
+    };
 
-    match channel_id.edit(&http, |c| c.topic(new_channel_topic)).await {
-        Ok(_) => Ok(()),
-        Err(why) => Err(eyre!("Error when updating channel topic: {}", why)),
+    if let Err(why) = store.save_members(&saved) {
+        log::error!("Error saving team members... {why}");
     }
 }
 
-pub fn jwt_still_valid(jwt: &JWTClaims) -> bool {
-    let local: DateTime<Local> = Local::now();
-    let unix_epoch = local.timestamp() as f64;
+/// Syncs `TEAM_MEMBERS` with the team list on HTB, adding anyone who wasn't known before to
+/// `UNWELCOMED_MEMBERS`, and saves both to the store.
+///
+/// Nobody is new on the first load, as there is nothing to compare against yet.
+pub async fn refresh_team_members(htb_api: &impl HtbApi, store: &SolveStore) -> Result<()> {
+    let first_load = TEAM_MEMBERS.is_empty();
+    let members = htb_api.list_team_members().await?;
+
+    let is_member = |id: &i64| members.iter().any(|member| member.id == *id);
+    TEAM_MEMBERS.retain(is_member);
+    UNWELCOMED_MEMBERS.retain(is_member);
+
+    for member in &members {
+        if TEAM_MEMBERS.insert(member.id) && !first_load {
+            log::info!("{} ({}) has joined the team", member.name, member.id);
+            UNWELCOMED_MEMBERS.insert(member.id);
+        }
+    }
+
+    save_team_members(store);
 
-    if unix_epoch > jwt.exp {
-        return false;
+    Ok(())
+}
+
+/// Forgets that a member still has to be welcomed, once they have been.
+pub fn mark_welcomed(store: &SolveStore, member_id: i64) {
+    if UNWELCOMED_MEMBERS.remove(&member_id).is_some() {
+        save_team_members(store);
     }
+}
+
+pub fn cache_solve(key: SolveKey) {
+    SOLVE_CACHE.entry(key.user_id).or_default().insert(key);
+}
+
+pub fn cached_solve_count() -> usize {
+    SOLVE_CACHE.iter().map(|solves| solves.len()).sum()
+}
+
+/// Whether the token will still be valid `margin` from now.
+pub fn jwt_still_valid(jwt: &JWTClaims, margin: Duration) -> bool {
+    let now = Local::now().timestamp() as f64;
 
-    true
+    now + margin.as_secs_f64() < jwt.exp
 }
 
 pub fn create_reqwest_client(api_key: &str, token_type: &str) -> Result<Client, Error> {
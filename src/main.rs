@@ -1,33 +1,59 @@
 #![warn(clippy::all, clippy::pedantic)]
 
 use async_mutex::Mutex;
-use futures::executor::block_on;
 use std::sync::Arc;
 use std::time::Duration;
-use std::{collections::HashSet, env};
+use std::{env, process};
 
+use chrono::Utc;
 use dotenv::dotenv;
-use hackthebot::htb::{api::new_htbapi_instance, api_types::HTBAPIConfig};
-use hackthebot::{load_solves_to_cache, ScheduleRunnerData};
+use hackthebot::config::Config;
+use hackthebot::htb::api::new_htbapi_instance;
+use hackthebot::htb::api_types::HtbAuth;
+use hackthebot::polling::{AdaptivePolling, RELEASE_CHECK_INTERVAL};
+use hackthebot::store::SolveStore;
+use hackthebot::{
+    load_solves_to_cache, load_team_members, scheduler::Scheduler, ScheduleRunnerData,
+};
 use serenity::async_trait;
 use serenity::client::Context;
 use serenity::client::EventHandler;
-use serenity::model::id::ChannelId;
+use serenity::model::application::interaction::Interaction;
+use serenity::model::id::GuildId;
 use serenity::prelude::GatewayIntents;
-use serenity::{http::Http, model::id::UserId, Client};
+use serenity::Client;
 use serenity::{model::gateway::Ready, model::Permissions};
-use tasks::{process_new_solves, process_rank_status};
+use tasks::{
+    process_machine_releases, process_new_solves, process_rank_status, RANK_JOB, RELEASES_JOB,
+    SOLVES_JOB,
+};
 
+use crate::commands::{handle_interaction, register_commands, Admins, SharedData};
+use crate::discord_utils::notifier_from_config;
+use crate::reload::Reloader;
+
+mod commands;
 mod discord_utils;
+mod reload;
 mod tasks;
 
-pub struct Handler;
+pub struct Handler {
+    data: SharedData,
+    admins: Admins,
+    /// Registers the slash commands for just this guild rather than globally.
+    guild_id: Option<GuildId>,
+    scheduler: Arc<Scheduler>,
+}
 
 #[async_trait]
 impl EventHandler for Handler {
     async fn ready(&self, ctx: Context, ready: Ready) {
         log::info!("{} is connected.", ready.user.name);
 
+        if let Err(why) = register_commands(&ctx.http, self.guild_id).await {
+            log::error!("Error registering slash commands: {why:?}");
+        }
+
         match ready.user.invite_url(&ctx.http, Permissions::empty()).await {
             Ok(url) => {
                 log::info!("Invite me using this url: {}", &url);
@@ -37,6 +63,33 @@ impl EventHandler for Handler {
             }
         };
     }
+
+    async fn interaction_create(&self, ctx: Context, interaction: Interaction) {
+        handle_interaction(&ctx, interaction, &self.data, &self.admins, &self.scheduler).await;
+    }
+}
+
+/// Prints a summary of the config, for `hackthebot check-config`.
+fn print_config_summary(config: &Config) {
+    match &config.path {
+        Some(path) => println!(
+            "Config from {} and the environment is valid.",
+            path.display()
+        ),
+        None => println!("Config from the environment is valid."),
+    }
+
+    let auth = match config.htb.auth {
+        HtbAuth::AppToken(_) => "app token",
+        HtbAuth::Password {
+            totp_secret: Some(_),
+            ..
+        } => "password with 2FA",
+        HtbAuth::Password { .. } => "password",
+    };
+    println!("  HTB team {} using {auth}", config.htb.team_id);
+    println!("  Announcing to {:?}", config.announcements.notifier);
+    println!("  Polling {:?}", config.polling);
 }
 
 #[tokio::main]
@@ -47,104 +100,183 @@ async fn main() {
     dotenv().ok();
     color_eyre::install().expect("Error when setting up color_eyre");
 
-    log::info!("Reading environment variables...");
-
-    let token =
-        env::var("DISCORD_TOKEN").expect("Expected a token in your environment (DISCORD_TOKEN)");
-    let owner_id_str = env::var("OWNER_ID").expect("Expected an OWNER_ID in your environment!");
-    let application_id =
-        env::var("APPLICATION_ID").expect("Expected an APPLICATION_ID in your environment!");
-    let application_id = application_id
-        .parse::<u64>()
-        .expect("Could not parse APPLICATION_ID!");
-    let owner_id = owner_id_str
-        .parse::<u64>()
-        .expect("Unable to parse OWNER_ID into u64... Did you put it in correctly?");
-    let team_id = env::var("HTB_TEAM_ID")
-        .expect("No HTB_TEAM_ID environment variable found!")
-        .parse::<i32>()
-        .expect("HTB_TEAM_ID isn't a number!");
-    let email = env::var("HTB_EMAIL").expect("No HTB_EMAIL environment variable found!");
-    let pass = env::var("HTB_PASSWORD").expect("No HTB_PASSWORD environment variable found!");
-    let htb_channel_id = env::var("HTB_CHANNEL_ID")
-        .expect("No HTB_CHANNEL_ID environment variable found!")
-        .parse::<u64>()
-        .expect("HTB_CHANNEL_ID environment variable was unable to be parsed to a u64...");
-
-    let mut owners = HashSet::new();
-    owners.insert(UserId(owner_id));
-
-    log::info!("Setting up discord client...");
+    log::info!("Reading config...");
 
-    let intents = GatewayIntents::all();
-    let mut client = Client::builder(&token, intents)
-        .event_handler(Handler)
-        .await
-        .expect("Error creating client");
-
-    let htb_config = HTBAPIConfig {
-        email,
-        password: pass,
-        team_id,
+    let config = match Config::load(|name| env::var(name).ok()) {
+        Ok(config) => config,
+        Err(why) => {
+            log::error!("{why}");
+            process::exit(1);
+        }
     };
 
+    if env::args().nth(1).as_deref() == Some("check-config") {
+        print_config_summary(&config);
+        return;
+    }
+
     log::info!("Initialising HTB API instance...");
 
-    let htb_api = new_htbapi_instance(htb_config)
+    let htb_api = new_htbapi_instance(config.htb.clone())
         .await
         .expect("Error when creating HTBApi instance...");
-    let http = Http::new_with_application_id(&token, application_id);
-    let channel_id = ChannelId(htb_channel_id);
+    let notifier = notifier_from_config(&config);
+    let store = SolveStore::new(&config.announcements.solve_store_path);
 
     log::info!("Building scheduler data...");
 
     let scheduler_data = ScheduleRunnerData {
         htb_api,
-        http,
-        channel_id,
+        notifier,
+        store,
+        catch_up_threshold: config.announcements.catch_up_threshold,
     };
 
     let threadsafe_data = Arc::new(Mutex::new(scheduler_data));
 
-    // Load the current solves into memory, which will be used for diffing later.
+    // Load the previously seen solves into memory, which will be used for diffing later.
     let data = threadsafe_data.lock().await;
-    if let Err(why) = load_solves_to_cache(&data.htb_api).await {
-        log::error!("Error loading categories to cache... {why}");
+    load_solves_or_exit(&data).await;
+    if let Err(why) = load_team_members(&data.htb_api, &data.store).await {
+        log::error!("Error loading team members... {why}");
     }
     std::mem::drop(data);
 
-    let data_arc1 = threadsafe_data.clone();
-    let data_arc2 = threadsafe_data.clone();
+    let polling = Arc::new(AdaptivePolling::new(config.polling.clone()));
+    let scheduler = Arc::new(build_scheduler(&threadsafe_data, &polling));
+    scheduler.start();
+
+    Reloader::new(
+        config.clone(),
+        threadsafe_data.clone(),
+        scheduler.clone(),
+        polling,
+    )
+    .watch();
+
+    log::info!("Setting up discord client...");
+
+    let handler = Handler {
+        data: threadsafe_data,
+        admins: Admins {
+            owners: config.discord.owners,
+            role: config.discord.admin_role,
+        },
+        guild_id: config.discord.guild_id,
+        scheduler,
+    };
+
+    let intents = GatewayIntents::all();
+    let mut client = Client::builder(&config.discord.token, intents)
+        .application_id(config.discord.application_id)
+        .event_handler(handler)
+        .await
+        .expect("Error creating client");
 
-    std::thread::spawn(move || loop {
-        let guard = block_on(data_arc1.lock());
+    if let Err(why) = client.start().await {
+        log::error!("Client error: {why:?}");
+    }
+}
+
+/// How many times loading the solves is tried before giving up.
+const SOLVE_LOAD_ATTEMPTS: u32 = 5;
+/// How long to wait between attempts, HTB being down is the most likely reason for a failure.
+const SOLVE_LOAD_RETRY_DELAY: Duration = Duration::from_secs(30);
+
+/// Fills the solve cache before anything is polled, exiting if it can't be done.
+///
+/// Polling without it would announce every solve HTB returns as new.
+async fn load_solves_or_exit(data: &ScheduleRunnerData) {
+    for attempt in 1..=SOLVE_LOAD_ATTEMPTS {
+        match load_solves_to_cache(&data.htb_api, &data.store).await {
+            Ok(()) => return,
+            Err(why) => log::error!(
+                "Error loading solves to cache (attempt {attempt}/{SOLVE_LOAD_ATTEMPTS})... {why}"
+            ),
+        }
 
-        log::info!("Processing current rank...");
-        if let Err(why) = process_rank_status(guard) {
-            log::error!("Error updating team rank status: {why:?}");
+        if attempt < SOLVE_LOAD_ATTEMPTS {
+            tokio::time::sleep(SOLVE_LOAD_RETRY_DELAY).await;
         }
+    }
 
-        // Sleep for a day.
-        std::thread::sleep(Duration::from_secs(86400));
-    });
+    log::error!("Unable to load solves to cache, exiting so old solves aren't announced as new");
+    process::exit(1);
+}
 
-    std::thread::spawn(move || loop {
-        let guard = block_on(data_arc2.lock());
+/// Polls for new solves and updates the team rank, also checking for machine releases when adaptive.
+fn build_scheduler(data: &SharedData, polling: &Arc<AdaptivePolling>) -> Scheduler {
+    let mut scheduler = Scheduler::default();
 
-        match process_new_solves(guard) {
-            Ok(num_new_solves) => {
-                log::info!("Successfully processed {num_new_solves} solves.");
+    let solves_data = data.clone();
+    let solves_polling = polling.clone();
+    let interval_polling = polling.clone();
+    scheduler.add_job_with_interval(
+        SOLVES_JOB,
+        move || interval_polling.interval(),
+        Duration::from_secs(5),
+        move || {
+            let data = solves_data.clone();
+            let polling = solves_polling.clone();
+            async move {
+                let num_new_solves = process_new_solves(&data).await?;
+
+                if num_new_solves > 0 {
+                    polling.record_solves(Utc::now());
+                }
+
+                Ok(format!("announced {num_new_solves} solves"))
             }
-            Err(why) => {
-                log::error!("Error processing new HTB solves: {why:?}");
+        },
+    );
+
+    let rank_data = data.clone();
+    let rank_polling = polling.clone();
+    scheduler.add_job_with_interval(
+        RANK_JOB,
+        move || rank_polling.rank_interval(),
+        Duration::from_secs(5 * 60),
+        move || {
+            let data = rank_data.clone();
+            async move {
+                process_rank_status(&data).await?;
+                Ok("updated the team rank".to_owned())
             }
-        }
+        },
+    );
 
-        // Sleep for 1 minute.
-        std::thread::sleep(Duration::from_secs(60));
-    });
+    // Always added, so adaptive polling can be turned on without a restart.
+    let releases_data = data.clone();
+    let releases_polling = polling.clone();
+    let rescheduler = scheduler.rescheduler();
+    scheduler.add_job(
+        RELEASES_JOB,
+        RELEASE_CHECK_INTERVAL,
+        Duration::from_secs(60),
+        move || {
+            let data = releases_data.clone();
+            let polling = releases_polling.clone();
+            let rescheduler = rescheduler.clone();
+            async move {
+                if !polling.is_adaptive() {
+                    return Ok("adaptive polling is off".to_owned());
+                }
 
-    if let Err(why) = client.start().await {
-        log::error!("Client error: {why:?}");
-    }
+                let interval = polling.interval();
+                let latest_release = process_machine_releases(&data, &polling).await?;
+
+                // Otherwise the solves job would only speed up after its current, slow, wait.
+                if polling.interval() != interval {
+                    rescheduler.reschedule();
+                }
+
+                Ok(match latest_release {
+                    Some(release) => format!("latest release was <t:{}:R>", release.timestamp()),
+                    None => "no machines are active".to_owned(),
+                })
+            }
+        },
+    );
+
+    scheduler
 }
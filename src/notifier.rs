@@ -0,0 +1,232 @@
+use std::fmt::Debug;
+#[cfg(any(test, feature = "fake"))]
+use std::sync::{
+    atomic::{AtomicBool, Ordering},
+    Mutex, PoisonError,
+};
+
+use async_trait::async_trait;
+#[cfg(any(test, feature = "fake"))]
+use color_eyre::eyre::eyre;
+use color_eyre::eyre::Result;
+use serenity::model::id::MessageId;
+
+use crate::{
+    htb::api_types::{RankStatsData, RecentTeamSolve, UserData},
+    Challenge,
+};
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct SolveToAnnounce {
+    pub solver: String,
+    pub user_id: i64,
+    pub solve_type: String,
+    pub first_blood: bool,
+    pub challenge: Challenge,
+}
+
+impl SolveToAnnounce {
+    /// A one line summary of the solve, such as "🏴 User has been owned by Huskeh on Love".
+    pub fn headline(&self) -> String {
+        let challenge = &self.challenge;
+        let solve_type = capitalise_first(&self.solve_type);
+
+        if self.first_blood && solve_type.eq("Challenge") {
+            format!(
+                "🩸 {} has taken first blood on {}!",
+                self.solver, challenge.name
+            )
+        } else if self.first_blood {
+            format!(
+                "🩸 {} has taken {} blood on {}!",
+                self.solver, self.solve_type, challenge.name
+            )
+        } else if solve_type.eq("Challenge") {
+            format!("🏴 {} has been solved by {}", challenge.name, self.solver)
+        } else {
+            format!(
+                "🏴 {} has been owned by {} on {}",
+                solve_type, self.solver, challenge.name
+            )
+        }
+    }
+}
+
+fn capitalise_first(s: &str) -> String {
+    let mut c = s.chars();
+    match c.next() {
+        None => String::new(),
+        Some(f) => f.to_uppercase().chain(c).collect(),
+    }
+}
+
+/// Wherever the bot tells the team about what's happening on HTB.
+///
+/// Announcements return the id of the message they were posted in, if there is one,
+/// so the solve store can link back to it.
+#[async_trait]
+pub trait Notifier: Debug + Send + Sync {
+    async fn announce_solve(&self, solve: &SolveToAnnounce) -> Result<Option<MessageId>>;
+
+    /// Posts many solves at once, used when catching up after the bot has been offline.
+    async fn post_digest(&self, solves: &[RecentTeamSolve]) -> Result<Option<MessageId>>;
+
+    async fn welcome_member(&self, user: &UserData, avatar_url: &str) -> Result<()>;
+
+    /// Shows the latest team rank, such as in the channel topic.
+    async fn update_status(&self, stats: &RankStatsData) -> Result<()>;
+}
+
+/// Writes everything to the log instead of sending it anywhere, handy for trying the bot out.
+#[derive(Debug, Default)]
+pub struct LogNotifier;
+
+#[async_trait]
+impl Notifier for LogNotifier {
+    async fn announce_solve(&self, solve: &SolveToAnnounce) -> Result<Option<MessageId>> {
+        log::info!("{} ({} points)", solve.headline(), solve.challenge.points);
+
+        Ok(None)
+    }
+
+    async fn post_digest(&self, solves: &[RecentTeamSolve]) -> Result<Option<MessageId>> {
+        log::info!("📰 {} solves while I was away", solves.len());
+
+        for solve in solves {
+            log::info!(
+                "  {} - {} ({})",
+                solve.user.name,
+                solve.name,
+                solve.solve_type
+            );
+        }
+
+        Ok(None)
+    }
+
+    async fn welcome_member(&self, user: &UserData, _avatar_url: &str) -> Result<()> {
+        log::info!("👋 Welcome to the team, {}!", user.name);
+
+        Ok(())
+    }
+
+    async fn update_status(&self, stats: &RankStatsData) -> Result<()> {
+        log::info!("Team rank {}, Points: {}", stats.rank, stats.points);
+
+        Ok(())
+    }
+}
+
+#[cfg(any(test, feature = "fake"))]
+#[derive(Debug, Clone, PartialEq)]
+pub enum Notification {
+    Solve(SolveToAnnounce),
+    Digest(Vec<RecentTeamSolve>),
+    Welcome(UserData),
+    Status { rank: i32, points: i32 },
+}
+
+/// Keeps every notification in memory, so tests can check what would have been sent.
+#[cfg(any(test, feature = "fake"))]
+#[derive(Debug, Default)]
+pub struct RecordingNotifier {
+    notifications: Mutex<Vec<Notification>>,
+    /// Makes every digest fail, like Discord rejecting one that's too long.
+    pub fail_digests: AtomicBool,
+}
+
+#[cfg(any(test, feature = "fake"))]
+impl RecordingNotifier {
+    /// Takes everything recorded so far, leaving it empty.
+    pub fn take(&self) -> Vec<Notification> {
+        std::mem::take(
+            &mut self
+                .notifications
+                .lock()
+                .unwrap_or_else(PoisonError::into_inner),
+        )
+    }
+
+    fn record(&self, notification: Notification) {
+        self.notifications
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .push(notification);
+    }
+}
+
+#[cfg(any(test, feature = "fake"))]
+#[async_trait]
+impl Notifier for RecordingNotifier {
+    async fn announce_solve(&self, solve: &SolveToAnnounce) -> Result<Option<MessageId>> {
+        self.record(Notification::Solve(solve.clone()));
+
+        Ok(None)
+    }
+
+    async fn post_digest(&self, solves: &[RecentTeamSolve]) -> Result<Option<MessageId>> {
+        if self.fail_digests.load(Ordering::Relaxed) {
+            return Err(eyre!("The digest was rejected"));
+        }
+
+        self.record(Notification::Digest(solves.to_vec()));
+
+        Ok(None)
+    }
+
+    async fn welcome_member(&self, user: &UserData, _avatar_url: &str) -> Result<()> {
+        self.record(Notification::Welcome(user.clone()));
+
+        Ok(())
+    }
+
+    async fn update_status(&self, stats: &RankStatsData) -> Result<()> {
+        self.record(Notification::Status {
+            rank: stats.rank,
+            points: stats.points,
+        });
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn solve(solve_type: &str, first_blood: bool) -> SolveToAnnounce {
+        SolveToAnnounce {
+            solver: "Huskeh".to_owned(),
+            user_id: 1,
+            solve_type: solve_type.to_owned(),
+            first_blood,
+            challenge: Challenge {
+                name: "Love".to_owned(),
+                points: 20,
+                challenge_type: "machine".to_owned(),
+                machine_avatar: None,
+                challenge_category: None,
+            },
+        }
+    }
+
+    #[test]
+    fn test_headline() {
+        assert_eq!(
+            solve("user", false).headline(),
+            "🏴 User has been owned by Huskeh on Love"
+        );
+        assert_eq!(
+            solve("root", true).headline(),
+            "🩸 Huskeh has taken root blood on Love!"
+        );
+        assert_eq!(
+            solve("challenge", false).headline(),
+            "🏴 Love has been solved by Huskeh"
+        );
+        assert_eq!(
+            solve("challenge", true).headline(),
+            "🩸 Huskeh has taken first blood on Love!"
+        );
+    }
+}
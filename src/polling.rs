@@ -0,0 +1,196 @@
+use std::{
+    sync::{Mutex, PoisonError},
+    time::Duration,
+};
+
+use chrono::{DateTime, Utc};
+
+use crate::htb::api_types::ListActiveMachinesData;
+
+pub static DEFAULT_SOLVE_POLL_INTERVAL: Duration = Duration::from_secs(60);
+pub static DEFAULT_RANK_UPDATE_INTERVAL: Duration = Duration::from_secs(24 * 60 * 60);
+/// How often active machines are checked for new releases when polling adaptively.
+pub static RELEASE_CHECK_INTERVAL: Duration = Duration::from_secs(15 * 60);
+
+/// How adaptive polling speeds up and slows down.
+#[derive(Debug, Clone, PartialEq)]
+pub struct AdaptiveConfig {
+    pub fast_interval: Duration,
+    pub slow_interval: Duration,
+    /// How long after a release polling stays fast.
+    pub release_window: Duration,
+    /// How long without any new solves before polling slows down.
+    pub quiet_after: Duration,
+}
+
+impl Default for AdaptiveConfig {
+    fn default() -> Self {
+        Self {
+            fast_interval: Duration::from_secs(20),
+            slow_interval: Duration::from_secs(5 * 60),
+            release_window: Duration::from_secs(4 * 60 * 60),
+            quiet_after: Duration::from_secs(12 * 60 * 60),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct PollingConfig {
+    pub solve_interval: Duration,
+    pub rank_interval: Duration,
+    /// Speeds up and slows down the solve poller when set.
+    pub adaptive: Option<AdaptiveConfig>,
+}
+
+/// When adaptive polling is enabled, polls for solves faster for a while after a machine is
+/// released, when the team is most likely to be hacking, and slower once nobody has solved
+/// anything for a while.
+///
+/// The config can be swapped out while the bot is running, without forgetting recent activity.
+#[derive(Debug)]
+pub struct AdaptivePolling {
+    config: Mutex<PollingConfig>,
+    latest_release: Mutex<Option<DateTime<Utc>>>,
+    latest_solve: Mutex<DateTime<Utc>>,
+}
+
+impl AdaptivePolling {
+    pub fn new(config: PollingConfig) -> Self {
+        Self {
+            config: Mutex::new(config),
+            latest_release: Mutex::new(None),
+            // Nothing is known about the team yet, so start off as if they'd just solved something.
+            latest_solve: Mutex::new(Utc::now()),
+        }
+    }
+
+    pub fn config(&self) -> PollingConfig {
+        // The config is only ever swapped out whole, so it's still usable if a holder panicked.
+        self.config
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .clone()
+    }
+
+    pub fn reconfigure(&self, config: PollingConfig) {
+        *self.config.lock().unwrap_or_else(PoisonError::into_inner) = config;
+    }
+
+    pub fn is_adaptive(&self) -> bool {
+        self.config().adaptive.is_some()
+    }
+
+    pub fn rank_interval(&self) -> Duration {
+        self.config().rank_interval
+    }
+
+    /// Remembers the most recent release, ignoring machines with an unreadable release date.
+    pub fn record_releases(&self, machines: &[ListActiveMachinesData]) -> Option<DateTime<Utc>> {
+        let latest = machines
+            .iter()
+            .filter_map(|machine| DateTime::parse_from_rfc3339(&machine.release).ok())
+            .map(|release| release.with_timezone(&Utc))
+            .max();
+
+        if let Ok(mut latest_release) = self.latest_release.lock() {
+            *latest_release = latest;
+        }
+
+        latest
+    }
+
+    pub fn record_solves(&self, at: DateTime<Utc>) {
+        if let Ok(mut latest_solve) = self.latest_solve.lock() {
+            *latest_solve = at;
+        }
+    }
+
+    pub fn interval(&self) -> Duration {
+        self.interval_at(Utc::now())
+    }
+
+    fn interval_at(&self, now: DateTime<Utc>) -> Duration {
+        let PollingConfig {
+            solve_interval,
+            adaptive,
+            ..
+        } = self.config();
+        let Some(adaptive) = adaptive else {
+            return solve_interval;
+        };
+
+        let since = |then: DateTime<Utc>| (now - then).to_std().unwrap_or_default();
+
+        let latest_release = self.latest_release.lock().ok().and_then(|release| *release);
+        if latest_release.is_some_and(|release| since(release) < adaptive.release_window) {
+            return adaptive.fast_interval;
+        }
+
+        match self.latest_solve.lock() {
+            Ok(latest_solve) if since(*latest_solve) >= adaptive.quiet_after => {
+                adaptive.slow_interval
+            }
+            _ => solve_interval,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::Duration as ChronoDuration;
+
+    use super::*;
+
+    fn machine_released(release: DateTime<Utc>) -> ListActiveMachinesData {
+        let data = include_str!("../test_resources/list_machines.json");
+        let mut machines: crate::htb::api_types::ListActiveMachines =
+            serde_json::from_str(data).unwrap();
+
+        let mut machine = machines.info.remove(0);
+        machine.release = release.to_rfc3339();
+        machine
+    }
+
+    #[test]
+    fn test_interval_follows_releases_and_activity() {
+        let polling = AdaptivePolling::new(PollingConfig {
+            solve_interval: Duration::from_secs(60),
+            rank_interval: DEFAULT_RANK_UPDATE_INTERVAL,
+            adaptive: Some(AdaptiveConfig::default()),
+        });
+        let now = Utc::now();
+
+        assert_eq!(polling.interval_at(now), Duration::from_secs(60));
+
+        polling.record_releases(&[machine_released(now - ChronoDuration::hours(1))]);
+        assert_eq!(polling.interval_at(now), Duration::from_secs(20));
+        assert_eq!(
+            polling.interval_at(now + ChronoDuration::hours(4)),
+            Duration::from_secs(60)
+        );
+
+        // Nobody has solved anything for half a day after the release window closed.
+        assert_eq!(
+            polling.interval_at(now + ChronoDuration::hours(13)),
+            Duration::from_secs(5 * 60)
+        );
+
+        polling.record_solves(now + ChronoDuration::hours(13));
+        assert_eq!(
+            polling.interval_at(now + ChronoDuration::hours(14)),
+            Duration::from_secs(60)
+        );
+
+        // Turning adaptive polling off sticks to the regular interval, whatever happened recently.
+        polling.reconfigure(PollingConfig {
+            solve_interval: Duration::from_secs(90),
+            adaptive: None,
+            ..polling.config()
+        });
+        assert_eq!(polling.interval_at(now), Duration::from_secs(90));
+        assert_eq!(
+            polling.interval_at(now + ChronoDuration::hours(13)),
+            Duration::from_secs(90)
+        );
+    }
+}
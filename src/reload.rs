@@ -0,0 +1,198 @@
+use std::{env, fs, sync::Arc, time::Duration, time::SystemTime};
+
+use hackthebot::{
+    config::Config, htb::api::new_htbapi_instance, polling::AdaptivePolling, scheduler::Scheduler,
+};
+use tokio::signal::unix::{signal, SignalKind};
+
+use crate::{commands::SharedData, discord_utils::notifier_from_config};
+
+/// How often the config file is checked for changes.
+const CONFIG_CHECK_INTERVAL: Duration = Duration::from_secs(5);
+
+/// Applies changes to the config while the bot is running, keeping the solve cache and
+/// everything else in memory.
+pub struct Reloader {
+    /// The config that is currently in use.
+    config: Config,
+    data: SharedData,
+    scheduler: Arc<Scheduler>,
+    polling: Arc<AdaptivePolling>,
+}
+
+/// Settings that are only read at startup, so changing them needs a restart.
+fn restart_required(old: &Config, new: &Config) -> Vec<&'static str> {
+    let (old_discord, new_discord) = (&old.discord, &new.discord);
+
+    [
+        ("discord.token", old_discord.token != new_discord.token),
+        (
+            "discord.application_id",
+            old_discord.application_id != new_discord.application_id,
+        ),
+        (
+            "discord.owner_ids",
+            old_discord.owners != new_discord.owners,
+        ),
+        (
+            "discord.admin_role_id",
+            old_discord.admin_role != new_discord.admin_role,
+        ),
+        (
+            "discord.guild_id",
+            old_discord.guild_id != new_discord.guild_id,
+        ),
+        (
+            "announcements.solve_store_path",
+            old.announcements.solve_store_path != new.announcements.solve_store_path,
+        ),
+    ]
+    .into_iter()
+    .filter_map(|(key, changed)| changed.then_some(key))
+    .collect()
+}
+
+fn modified(config: &Config) -> Option<SystemTime> {
+    let path = config.path.as_ref()?;
+
+    fs::metadata(path).and_then(|meta| meta.modified()).ok()
+}
+
+impl Reloader {
+    pub fn new(
+        config: Config,
+        data: SharedData,
+        scheduler: Arc<Scheduler>,
+        polling: Arc<AdaptivePolling>,
+    ) -> Self {
+        Self {
+            config,
+            data,
+            scheduler,
+            polling,
+        }
+    }
+
+    /// Reloads whenever the config file changes or the bot receives a SIGHUP.
+    pub fn watch(mut self) {
+        tokio::spawn(async move {
+            let mut hangups = match signal(SignalKind::hangup()) {
+                Ok(hangups) => Some(hangups),
+                Err(why) => {
+                    log::error!(
+                        "Unable to listen for SIGHUP, only watching the config file: {why}"
+                    );
+                    None
+                }
+            };
+            let mut checks = tokio::time::interval(CONFIG_CHECK_INTERVAL);
+            let mut last_modified = modified(&self.config);
+
+            loop {
+                tokio::select! {
+                    Some(()) = async { hangups.as_mut()?.recv().await } => {
+                        log::info!("Received SIGHUP, reloading the config...");
+                    }
+                    _ = checks.tick() => {
+                        if modified(&self.config) == last_modified {
+                            continue;
+                        }
+
+                        log::info!("The config file has changed, reloading...");
+                    }
+                }
+
+                last_modified = modified(&self.config);
+                self.reload().await;
+            }
+        });
+    }
+
+    /// Reads the config again and applies whatever changed, keeping the current config if the
+    /// new one has any problems.
+    pub async fn reload(&mut self) {
+        let mut config = match Config::load(|name| env::var(name).ok()) {
+            Ok(config) => config,
+            Err(why) => {
+                log::error!("Keeping the current config... {why}");
+                return;
+            }
+        };
+
+        for key in restart_required(&self.config, &config) {
+            log::warn!("{key} has changed, but won't be used until the bot is restarted");
+        }
+
+        if config.htb != self.config.htb {
+            match new_htbapi_instance(config.htb.clone()).await {
+                Ok(htb_api) => {
+                    self.data.lock().await.htb_api = htb_api;
+                    log::info!("Logged in to HTB with the new settings");
+                }
+                Err(why) => {
+                    log::error!("Error logging in to HTB with the new settings, keeping the old ones... {why}");
+                    config.htb = self.config.htb.clone();
+                }
+            }
+        }
+
+        {
+            let mut data = self.data.lock().await;
+
+            if config.announcements.notifier != self.config.announcements.notifier {
+                data.notifier = notifier_from_config(&config);
+                log::info!(
+                    "Announcing to {:?} from now on",
+                    config.announcements.notifier
+                );
+            }
+
+            data.catch_up_threshold = config.announcements.catch_up_threshold;
+        }
+
+        if config.polling != self.config.polling {
+            self.polling.reconfigure(config.polling.clone());
+            self.scheduler.reschedule();
+            log::info!("Polling with {:?} from now on", config.polling);
+        }
+
+        self.config = config;
+        log::info!("Reloaded the config");
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use hackthebot::config::NotifierConfig;
+    use serenity::model::id::{ChannelId, UserId};
+
+    use super::*;
+
+    #[test]
+    fn test_restart_required() {
+        let old = Config::parse(None, None, |name| match name {
+            "DISCORD_TOKEN" => Some("discord-token".to_owned()),
+            "APPLICATION_ID" | "OWNER_ID" | "HTB_TEAM_ID" | "HTB_CHANNEL_ID" => {
+                Some("1".to_owned())
+            }
+            "HTB_APP_TOKEN" => Some("app-token".to_owned()),
+            _ => None,
+        })
+        .unwrap();
+
+        let mut new = old.clone();
+        new.announcements.notifier = NotifierConfig::Discord {
+            channel_id: ChannelId(2),
+            blood_role: None,
+        };
+        new.polling.solve_interval = Duration::from_secs(1);
+        assert!(restart_required(&old, &new).is_empty());
+
+        new.discord.owners.insert(UserId(2));
+        new.discord.token = "rotated".to_owned();
+        assert_eq!(
+            restart_required(&old, &new),
+            vec!["discord.token", "discord.owner_ids"]
+        );
+    }
+}
@@ -0,0 +1,396 @@
+use std::{
+    fmt,
+    future::Future,
+    pin::Pin,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc, Mutex,
+    },
+    time::{Duration, Instant},
+};
+
+use chrono::{DateTime, Local};
+use color_eyre::eyre::{eyre, Result};
+use rand::Rng;
+use tokio::sync::Notify;
+
+use crate::htb::error::HtbApiError;
+
+type JobFuture = Pin<Box<dyn Future<Output = Result<String>> + Send>>;
+
+/// How a single run of a job went.
+#[derive(Debug, Clone)]
+pub struct JobRun {
+    pub finished_at: DateTime<Local>,
+    pub took: Duration,
+    /// A summary of what the job did, or why it failed.
+    pub outcome: Result<String, String>,
+}
+
+/// Something the scheduler runs every `interval`, plus up to `jitter` so jobs don't line up.
+///
+/// The interval is asked for again after every run, and whenever the scheduler is told the
+/// intervals have changed, so it can change while the bot is running.
+pub struct Job {
+    name: &'static str,
+    interval: Box<dyn Fn() -> Duration + Send + Sync>,
+    jitter: Duration,
+    run: Box<dyn Fn() -> JobFuture + Send + Sync>,
+    paused: AtomicBool,
+    running: AtomicBool,
+    last_run: Mutex<Option<JobRun>>,
+    last_error: Mutex<Option<JobRun>>,
+    /// Shared by every job of the scheduler.
+    rescheduled: Arc<Notify>,
+}
+
+impl fmt::Debug for Job {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Job")
+            .field("name", &self.name)
+            .field("interval", &self.interval())
+            .field("jitter", &self.jitter)
+            .field("paused", &self.paused)
+            .field("running", &self.running)
+            .finish_non_exhaustive()
+    }
+}
+
+/// Clears the running flag even if the job panics, so it isn't stuck forever.
+struct RunningGuard<'a>(&'a AtomicBool);
+
+impl Drop for RunningGuard<'_> {
+    fn drop(&mut self) {
+        self.0.store(false, Ordering::SeqCst);
+    }
+}
+
+impl Job {
+    pub fn name(&self) -> &'static str {
+        self.name
+    }
+
+    pub fn interval(&self) -> Duration {
+        (self.interval)()
+    }
+
+    pub fn is_paused(&self) -> bool {
+        self.paused.load(Ordering::SeqCst)
+    }
+
+    /// Paused jobs are skipped on schedule, but can still be run by hand.
+    pub fn set_paused(&self, paused: bool) {
+        self.paused.store(paused, Ordering::SeqCst);
+    }
+
+    pub fn is_running(&self) -> bool {
+        self.running.load(Ordering::SeqCst)
+    }
+
+    pub fn last_run(&self) -> Option<JobRun> {
+        self.last_run.lock().ok().and_then(|run| run.clone())
+    }
+
+    /// The most recent failed run, which sticks around after the job starts succeeding again.
+    pub fn last_error(&self) -> Option<JobRun> {
+        self.last_error.lock().ok().and_then(|run| run.clone())
+    }
+
+    /// Runs the job unless it is already running.
+    async fn run(&self) -> Result<String> {
+        if self
+            .running
+            .compare_exchange(false, true, Ordering::SeqCst, Ordering::SeqCst)
+            .is_err()
+        {
+            return Err(eyre!("The {} job is already running", self.name));
+        }
+        let _running = RunningGuard(&self.running);
+
+        let started = Instant::now();
+        let result = (self.run)().await;
+
+        let run = JobRun {
+            finished_at: Local::now(),
+            took: started.elapsed(),
+            outcome: result
+                .as_ref()
+                .map(Clone::clone)
+                .map_err(|why| format!("{why:#}")),
+        };
+
+        if run.outcome.is_err() {
+            if let Ok(mut last_error) = self.last_error.lock() {
+                *last_error = Some(run.clone());
+            }
+        }
+        if let Ok(mut last_run) = self.last_run.lock() {
+            *last_run = Some(run);
+        }
+
+        result
+    }
+
+    /// How long to wait after a run, backing off for longer if HTB asked us to.
+    fn next_delay(&self, result: &Result<String>) -> Duration {
+        let jitter = self.jitter.mul_f64(rand::thread_rng().gen_range(0.0..=1.0));
+        let delay = self.interval() + jitter;
+
+        let retry_after =
+            result
+                .as_ref()
+                .err()
+                .and_then(|why| match why.downcast_ref::<HtbApiError>() {
+                    Some(HtbApiError::RateLimited { retry_after }) => *retry_after,
+                    _ => None,
+                });
+
+        match retry_after {
+            Some(retry_after) if retry_after > delay => {
+                log::warn!(
+                    "HTB is rate limiting us, backing off the {} job for {retry_after:?}...",
+                    self.name
+                );
+                retry_after
+            }
+            _ => delay,
+        }
+    }
+}
+
+/// Tells every job of a scheduler to work out when it next runs again, which jobs can hold on to
+/// as it can be taken before they're added.
+#[derive(Debug, Clone, Default)]
+pub struct Rescheduler(Arc<Notify>);
+
+impl Rescheduler {
+    pub fn reschedule(&self) {
+        self.0.notify_waiters();
+    }
+}
+
+/// Runs named jobs on the tokio runtime, never running the same job twice at once.
+#[derive(Debug, Default)]
+pub struct Scheduler {
+    jobs: Vec<Arc<Job>>,
+    rescheduler: Rescheduler,
+}
+
+impl Scheduler {
+    pub fn add_job<F, Fut>(
+        &mut self,
+        name: &'static str,
+        interval: Duration,
+        jitter: Duration,
+        run: F,
+    ) where
+        F: Fn() -> Fut + Send + Sync + 'static,
+        Fut: Future<Output = Result<String>> + Send + 'static,
+    {
+        self.add_job_with_interval(name, move || interval, jitter, run);
+    }
+
+    /// Adds a job whose interval is worked out again after every run.
+    pub fn add_job_with_interval<I, F, Fut>(
+        &mut self,
+        name: &'static str,
+        interval: I,
+        jitter: Duration,
+        run: F,
+    ) where
+        I: Fn() -> Duration + Send + Sync + 'static,
+        F: Fn() -> Fut + Send + Sync + 'static,
+        Fut: Future<Output = Result<String>> + Send + 'static,
+    {
+        self.jobs.push(Arc::new(Job {
+            name,
+            interval: Box::new(interval),
+            jitter,
+            run: Box::new(move || Box::pin(run())),
+            paused: AtomicBool::new(false),
+            running: AtomicBool::new(false),
+            last_run: Mutex::new(None),
+            last_error: Mutex::new(None),
+            rescheduled: self.rescheduler.0.clone(),
+        }));
+    }
+
+    pub fn jobs(&self) -> impl Iterator<Item = &Job> {
+        self.jobs.iter().map(AsRef::as_ref)
+    }
+
+    pub fn job(&self, name: &str) -> Option<&Job> {
+        self.jobs().find(|job| job.name == name)
+    }
+
+    /// Runs a job straight away, failing if it's unknown or already running.
+    pub async fn run_now(&self, name: &str) -> Result<String> {
+        let job = self.job(name).ok_or_else(|| eyre!("Unknown job {name}"))?;
+
+        job.run().await
+    }
+
+    /// Works out when every waiting job next runs again, for after their intervals have changed.
+    pub fn reschedule(&self) {
+        self.rescheduler.reschedule();
+    }
+
+    /// A handle for rescheduling from within a job, where the scheduler itself isn't available.
+    pub fn rescheduler(&self) -> Rescheduler {
+        self.rescheduler.clone()
+    }
+
+    /// Spawns a task for every job, each of which runs straight away and then on its interval.
+    pub fn start(&self) {
+        for job in &self.jobs {
+            let job = job.clone();
+
+            tokio::spawn(async move {
+                loop {
+                    let result = if job.is_paused() {
+                        log::info!("The {} job is paused, skipping...", job.name);
+                        Ok(String::new())
+                    } else {
+                        let result = job.run().await;
+
+                        match &result {
+                            Ok(summary) => log::info!("Finished the {} job: {summary}", job.name),
+                            Err(why) => log::error!("Error running the {} job: {why:?}", job.name),
+                        }
+
+                        result
+                    };
+
+                    let finished = tokio::time::Instant::now();
+                    let mut delay = job.next_delay(&result);
+
+                    loop {
+                        tokio::select! {
+                            () = tokio::time::sleep_until(finished + delay) => break,
+                            () = job.rescheduled.notified() => delay = job.next_delay(&result),
+                        }
+                    }
+                }
+            });
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_run_now_records_state() {
+        let mut scheduler = Scheduler::default();
+        scheduler.add_job("ok", Duration::from_secs(60), Duration::ZERO, || async {
+            Ok("done".to_owned())
+        });
+        scheduler.add_job(
+            "broken",
+            Duration::from_secs(60),
+            Duration::ZERO,
+            || async { Err(eyre!("oops")) },
+        );
+
+        assert_eq!(scheduler.run_now("ok").await.unwrap(), "done");
+        assert!(scheduler.run_now("broken").await.is_err());
+        assert!(scheduler.run_now("missing").await.is_err());
+
+        let ok = scheduler.job("ok").unwrap();
+        assert_eq!(ok.last_run().unwrap().outcome, Ok("done".to_owned()));
+        assert!(ok.last_error().is_none());
+
+        let broken = scheduler.job("broken").unwrap();
+        assert_eq!(broken.last_error().unwrap().outcome, Err("oops".to_owned()));
+    }
+
+    #[tokio::test]
+    async fn test_jobs_never_overlap() {
+        let release = Arc::new(Notify::new());
+        let waiting = release.clone();
+
+        let mut scheduler = Scheduler::default();
+        scheduler.add_job("slow", Duration::from_secs(60), Duration::ZERO, move || {
+            let waiting = waiting.clone();
+            async move {
+                waiting.notified().await;
+                Ok("finally".to_owned())
+            }
+        });
+        let scheduler = Arc::new(scheduler);
+
+        let first = tokio::spawn({
+            let scheduler = scheduler.clone();
+            async move { scheduler.run_now("slow").await }
+        });
+        while !scheduler.job("slow").unwrap().is_running() {
+            tokio::task::yield_now().await;
+        }
+
+        assert!(scheduler.run_now("slow").await.is_err());
+
+        release.notify_one();
+        assert_eq!(first.await.unwrap().unwrap(), "finally");
+        assert!(!scheduler.job("slow").unwrap().is_running());
+    }
+
+    #[tokio::test]
+    async fn test_reschedule_picks_up_new_intervals() {
+        let interval = Arc::new(Mutex::new(Duration::from_secs(60 * 60)));
+        let ran = Arc::new(Notify::new());
+
+        let mut scheduler = Scheduler::default();
+        let rescheduler = scheduler.rescheduler();
+        scheduler.add_job_with_interval(
+            "changing",
+            {
+                let interval = interval.clone();
+                move || *interval.lock().unwrap()
+            },
+            Duration::ZERO,
+            {
+                let ran = ran.clone();
+                move || {
+                    ran.notify_one();
+                    async { Ok(String::new()) }
+                }
+            },
+        );
+        scheduler.start();
+
+        // Runs straight away, and then wouldn't run again for an hour.
+        ran.notified().await;
+        while scheduler.job("changing").unwrap().is_running() {
+            tokio::task::yield_now().await;
+        }
+
+        *interval.lock().unwrap() = Duration::from_millis(10);
+        rescheduler.reschedule();
+
+        tokio::time::timeout(Duration::from_secs(5), ran.notified())
+            .await
+            .expect("the job should have run again on its new interval");
+    }
+
+    #[test]
+    fn test_rate_limits_push_back_the_next_run() {
+        let mut scheduler = Scheduler::default();
+        scheduler.add_job(
+            "solves",
+            Duration::from_secs(60),
+            Duration::from_secs(5),
+            || async { Ok(String::new()) },
+        );
+        let job = scheduler.job("solves").unwrap();
+
+        let delay = job.next_delay(&Ok(String::new()));
+        assert!(delay >= Duration::from_secs(60) && delay <= Duration::from_secs(65));
+
+        let rate_limited = Err(HtbApiError::RateLimited {
+            retry_after: Some(Duration::from_secs(600)),
+        }
+        .into());
+        assert_eq!(job.next_delay(&rate_limited), Duration::from_secs(600));
+    }
+}
@@ -0,0 +1,353 @@
+use std::{
+    fs::{self, File, OpenOptions},
+    io::{BufRead, BufReader, ErrorKind, Write},
+    path::{Path, PathBuf},
+};
+
+use chrono::Utc;
+use color_eyre::eyre::{eyre, Error, Result};
+use serde::{Deserialize, Serialize};
+use serenity::model::id::MessageId;
+
+use crate::{htb::api_types::RecentTeamSolve, SolveKey};
+
+pub static DEFAULT_STORE_PATH: &str = "solves.jsonl";
+
+/// A single line of the solve journal.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct StoredSolve {
+    pub key: SolveKey,
+    pub solve: RecentTeamSolve,
+    /// The discord message the solve was announced in, `None` for solves
+    /// that were seeded from HTB without being announced.
+    pub message_id: Option<u64>,
+    /// Unix timestamp of when the entry was written.
+    pub recorded_at: i64,
+}
+
+/// Journal entries written before solves were identified by a `SolveKey`.
+#[derive(Debug, Deserialize)]
+struct LegacyStoredSolve {
+    solve: RecentTeamSolve,
+    message_id: Option<u64>,
+    recorded_at: i64,
+}
+
+impl From<LegacyStoredSolve> for StoredSolve {
+    fn from(legacy: LegacyStoredSolve) -> Self {
+        Self {
+            key: SolveKey::from(&legacy.solve),
+            solve: legacy.solve,
+            message_id: legacy.message_id,
+            recorded_at: legacy.recorded_at,
+        }
+    }
+}
+
+/// The team members as they were last seen on HTB, saved next to the journal.
+#[derive(Debug, Default, PartialEq, Serialize, Deserialize)]
+pub struct SavedMembers {
+    pub members: Vec<i64>,
+    /// Members who joined the team but haven't been welcomed yet, which happens on their first solve.
+    #[serde(default)]
+    pub unwelcomed: Vec<i64>,
+}
+
+/// Append-only journal of every solve the bot has seen, one JSON object per line.
+///
+/// The journal is read back into `SOLVE_CACHE` on startup, so solves that happen
+/// while the bot is offline are still announced once it comes back. The team members
+/// are kept next to it for the same reason, so anyone who joined in the meantime is
+/// still welcomed.
+#[derive(Debug, Clone)]
+pub struct SolveStore {
+    path: PathBuf,
+}
+
+impl SolveStore {
+    pub fn new(path: impl Into<PathBuf>) -> Self {
+        Self { path: path.into() }
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    pub fn exists(&self) -> bool {
+        self.path.exists()
+    }
+
+    /// Where the team members are kept, e.g. `solves.members.json` next to `solves.jsonl`.
+    pub fn members_path(&self) -> PathBuf {
+        self.path.with_extension("members.json")
+    }
+
+    /// Reads every entry in the journal, rewriting it first if it still contains legacy entries
+    /// or unreadable lines.
+    pub fn load(&self) -> Result<Vec<StoredSolve>, Error> {
+        let file = File::open(&self.path).map_err(|why| {
+            eyre!(
+                "Unable to open solve store {}: {}",
+                self.path.display(),
+                why
+            )
+        })?;
+
+        let mut solves = Vec::new();
+        let mut num_legacy = 0;
+        let mut num_skipped = 0;
+
+        for (line_number, line) in BufReader::new(file).lines().enumerate() {
+            let line = line?;
+
+            if line.trim().is_empty() {
+                continue;
+            }
+
+            if let Ok(stored) = serde_json::from_str::<StoredSolve>(&line) {
+                solves.push(stored);
+                continue;
+            }
+
+            // A torn write at the end of the journal shouldn't stop the bot from starting.
+            match serde_json::from_str::<LegacyStoredSolve>(&line) {
+                Ok(legacy) => {
+                    solves.push(legacy.into());
+                    num_legacy += 1;
+                }
+                Err(why) => {
+                    log::warn!(
+                        "Skipping unreadable entry on line {} of {}: {why}",
+                        line_number + 1,
+                        self.path.display()
+                    );
+                    num_skipped += 1;
+                }
+            }
+        }
+
+        // Left in place, the next entry would be appended onto a torn line and be unreadable too.
+        if num_legacy > 0 || num_skipped > 0 {
+            self.rewrite(&solves)?;
+            log::info!(
+                "Rewrote {} after migrating {num_legacy} legacy entries and dropping {num_skipped} unreadable ones",
+                self.path.display()
+            );
+        }
+
+        Ok(solves)
+    }
+
+    /// Atomically replaces the journal with the given entries.
+    fn rewrite(&self, solves: &[StoredSolve]) -> Result<()> {
+        let mut contents = String::new();
+        for stored in solves {
+            contents.push_str(&serde_json::to_string(stored)?);
+            contents.push('\n');
+        }
+
+        write_atomically(&self.path, contents)
+    }
+
+    /// Creates the journal from solves that were never announced, all at once, so a failure
+    /// part of the way through can't leave a journal that only has some of them.
+    pub fn seed(&self, solves: &[RecentTeamSolve]) -> Result<()> {
+        let recorded_at = Utc::now().timestamp();
+        let entries = solves
+            .iter()
+            .map(|solve| StoredSolve {
+                key: SolveKey::from(solve),
+                solve: solve.clone(),
+                message_id: None,
+                recorded_at,
+            })
+            .collect::<Vec<_>>();
+
+        self.rewrite(&entries)
+    }
+
+    /// The team members that were last saved, `None` if they never have been.
+    pub fn load_members(&self) -> Result<Option<SavedMembers>> {
+        let path = self.members_path();
+
+        let contents = match fs::read_to_string(&path) {
+            Ok(contents) => contents,
+            Err(why) if why.kind() == ErrorKind::NotFound => return Ok(None),
+            Err(why) => {
+                return Err(eyre!(
+                    "Unable to read team members from {}: {}",
+                    path.display(),
+                    why
+                ))
+            }
+        };
+
+        Ok(Some(serde_json::from_str(&contents)?))
+    }
+
+    pub fn save_members(&self, members: &SavedMembers) -> Result<()> {
+        write_atomically(&self.members_path(), serde_json::to_string(members)?)
+    }
+
+    pub fn record(&self, solve: &RecentTeamSolve, message_id: Option<MessageId>) -> Result<()> {
+        let entry = StoredSolve {
+            key: SolveKey::from(solve),
+            solve: solve.clone(),
+            message_id: message_id.map(|id| id.0),
+            recorded_at: Utc::now().timestamp(),
+        };
+
+        let mut line = serde_json::to_string(&entry)?;
+        line.push('\n');
+
+        let mut file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&self.path)?;
+        file.write_all(line.as_bytes())?;
+        file.sync_data()?;
+
+        Ok(())
+    }
+}
+
+/// Writes to a temporary file first, so a crash can't leave `path` half written.
+fn write_atomically(path: &Path, contents: String) -> Result<()> {
+    let mut temp_path = path.to_owned().into_os_string();
+    temp_path.push(".tmp");
+
+    fs::write(&temp_path, contents)?;
+    fs::rename(&temp_path, path)?;
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use tempfile::TempDir;
+
+    use super::*;
+
+    /// The directory is deleted along with the store when it's dropped, even if the test fails.
+    fn temp_store() -> (TempDir, SolveStore) {
+        let dir = TempDir::new().unwrap();
+        let store = SolveStore::new(dir.path().join(DEFAULT_STORE_PATH));
+
+        (dir, store)
+    }
+
+    fn recent_activity() -> Vec<RecentTeamSolve> {
+        let data = include_str!("../test_resources/recent_activity.json");
+        serde_json::from_str(data).unwrap()
+    }
+
+    #[test]
+    fn test_record_and_load_round_trip() {
+        let (_dir, store) = temp_store();
+        assert!(!store.exists());
+
+        let solves = recent_activity();
+        store.record(&solves[0], None).unwrap();
+        store.record(&solves[1], Some(MessageId(42))).unwrap();
+
+        let loaded = store.load().unwrap();
+
+        assert_eq!(loaded.len(), 2);
+        assert_eq!(loaded[0].solve, solves[0]);
+        assert_eq!(loaded[0].message_id, None);
+        assert_eq!(loaded[1].solve, solves[1]);
+        assert_eq!(loaded[1].message_id, Some(42));
+    }
+
+    #[test]
+    fn test_seed_writes_every_solve_at_once() {
+        let (_dir, store) = temp_store();
+
+        let solves = recent_activity();
+        store.seed(&solves).unwrap();
+
+        let mut temp_path = store.path().to_owned().into_os_string();
+        temp_path.push(".tmp");
+        assert!(!Path::new(&temp_path).exists());
+
+        let loaded = store.load().unwrap();
+
+        assert_eq!(loaded.len(), solves.len());
+        assert!(loaded.iter().all(|stored| stored.message_id.is_none()));
+    }
+
+    #[test]
+    fn test_members_round_trip() {
+        let (_dir, store) = temp_store();
+        assert_eq!(store.load_members().unwrap(), None);
+        assert_eq!(
+            store.members_path().file_name().unwrap(),
+            "solves.members.json"
+        );
+
+        let members = SavedMembers {
+            members: vec![66487, 1337],
+            unwelcomed: vec![1337],
+        };
+        store.save_members(&members).unwrap();
+
+        assert_eq!(store.load_members().unwrap(), Some(members));
+    }
+
+    #[test]
+    fn test_load_skips_torn_lines() {
+        let (_dir, store) = temp_store();
+
+        let solves = recent_activity();
+        store.record(&solves[0], None).unwrap();
+        fs::write(
+            store.path(),
+            format!("{}{{\"solve\":", fs::read_to_string(store.path()).unwrap()),
+        )
+        .unwrap();
+
+        let loaded = store.load().unwrap();
+
+        assert_eq!(loaded.len(), 1);
+
+        // The torn line is gone, so the next entry doesn't end up on the end of it.
+        store.record(&solves[1], None).unwrap();
+
+        let loaded = store.load().unwrap();
+
+        assert_eq!(loaded.len(), 2);
+        assert_eq!(loaded[1].solve, solves[1]);
+    }
+
+    #[test]
+    fn test_load_migrates_legacy_entries() {
+        let (_dir, store) = temp_store();
+
+        let solves = recent_activity();
+        let legacy = serde_json::json!({
+            "solve": solves[1],
+            "message_id": 42,
+            "recorded_at": 0,
+        });
+        fs::write(store.path(), format!("{legacy}\n")).unwrap();
+
+        let loaded = store.load().unwrap();
+        let migrated = fs::read_to_string(store.path()).unwrap();
+
+        let expected_key = SolveKey {
+            user_id: 66487,
+            object_type: "machine".to_owned(),
+            object_id: 315,
+            solve_type: "root".to_owned(),
+        };
+
+        assert_eq!(loaded.len(), 1);
+        assert_eq!(loaded[0].key, expected_key);
+        assert_eq!(loaded[0].message_id, Some(42));
+
+        let rewritten: StoredSolve = serde_json::from_str(migrated.trim()).unwrap();
+        assert_eq!(rewritten.key, expected_key);
+    }
+}
@@ -1,67 +1,367 @@
-use async_mutex::MutexGuard;
 use color_eyre::eyre::Error;
+use hackthebot::htb::{api::HtbApi, error::HtbApiError, rate_limit::Priority};
 use hackthebot::Challenge;
-use hackthebot::ScheduleRunnerData;
+use hackthebot::SolveKey;
 
-use hackthebot::update_htb_channel_topic_with_stats;
-use hackthebot::SOLVE_CACHE;
+use chrono::{DateTime, Utc};
+use hackthebot::htb::api_types::RecentTeamSolve;
+use hackthebot::notifier::{Notifier, SolveToAnnounce};
+use hackthebot::polling::AdaptivePolling;
+use hackthebot::store::SolveStore;
+use hackthebot::{
+    cache_solve, mark_welcomed, refresh_team_members, SOLVE_CACHE, TEAM_MEMBERS, UNWELCOMED_MEMBERS,
+};
 
-use crate::discord_utils::{announce_solve, SolveToAnnounce};
+use crate::commands::{htb_api, SharedData};
 
-#[tokio::main]
-pub async fn process_rank_status(mut data: MutexGuard<ScheduleRunnerData>) -> Result<(), Error> {
-    data.htb_api.handle_token_renewal().await?;
-    let latest_rank = data.htb_api.get_team_rank().await?;
+pub const SOLVES_JOB: &str = "solves";
+pub const RANK_JOB: &str = "rank";
+pub const RELEASES_JOB: &str = "releases";
 
-    if let Err(why) =
-        update_htb_channel_topic_with_stats(&latest_rank.data, &data.channel_id, &data.http).await
-    {
-        log::error!("Error when updating the HTB channel topic... {why}");
+/// Tries to fix whatever caused a request to HTB to fail, returning whether it's worth retrying.
+async fn recover_from<A: HtbApi>(data: &SharedData<A>, htb_api: &mut A, why: &HtbApiError) -> bool {
+    match why {
+        HtbApiError::Unauthorized { .. } => {
+            log::warn!("HTB rejected our token, logging in again...");
+
+            let mut data = data.lock().await;
+            match data.htb_api.relogin().await {
+                Ok(()) => {
+                    *htb_api = data.htb_api.clone();
+                    true
+                }
+                Err(why) => {
+                    log::error!("Error logging in to HTB again: {why}");
+                    false
+                }
+            }
+        }
+        HtbApiError::Decode { .. } => {
+            log::error!("HTB's API has changed and the bot needs updating: {why}");
+            false
+        }
+        _ => false,
+    }
+}
+
+pub async fn process_rank_status<A: HtbApi>(data: &SharedData<A>) -> Result<(), Error> {
+    let mut htb_api = htb_api(data, Priority::High).await?;
+    let latest_rank = match htb_api.get_team_rank().await {
+        Err(why) if recover_from(data, &mut htb_api, &why).await => htb_api.get_team_rank().await?,
+        result => result?,
+    };
+
+    let notifier = data.lock().await.notifier.clone();
+    if let Err(why) = notifier.update_status(&latest_rank.data).await {
+        log::error!("Error when updating the team status... {why}");
     }
 
     Ok(())
 }
 
-#[tokio::main]
-pub async fn process_new_solves(mut data: MutexGuard<ScheduleRunnerData>) -> Result<usize, Error> {
-    data.htb_api.handle_token_renewal().await?;
-    let team_activity = data.htb_api.get_recent_team_activity().await?;
-    let mut num_new_solves = 0;
+/// Checks for newly released machines, so solves are polled for more often while they're fresh.
+pub async fn process_machine_releases<A: HtbApi>(
+    data: &SharedData<A>,
+    polling: &AdaptivePolling,
+) -> Result<Option<DateTime<Utc>>, Error> {
+    let htb_api = htb_api(data, Priority::High).await?;
+    let machines = htb_api.list_active_machines().await?;
+
+    Ok(polling.record_releases(&machines.info))
+}
+
+async fn post_digest(
+    notifier: &dyn Notifier,
+    store: &SolveStore,
+    solves: Vec<RecentTeamSolve>,
+) -> Result<usize, Error> {
+    let message_id = notifier.post_digest(&solves).await?;
+    let num_solves = solves.len();
+
+    for solve in solves {
+        if let Err(why) = store.record(&solve, message_id) {
+            log::error!("Error when persisting solve {solve:#?}, err: {why}");
+        }
+
+        cache_solve(SolveKey::from(&solve));
+    }
+
+    Ok(num_solves)
+}
 
-    for solve in team_activity {
+pub async fn process_new_solves<A: HtbApi>(data: &SharedData<A>) -> Result<usize, Error> {
+    let mut htb_api = htb_api(data, Priority::High).await?;
+    let team_activity = match htb_api.get_recent_team_activity().await {
+        Err(why) if recover_from(data, &mut htb_api, &why).await => {
+            htb_api.get_recent_team_activity().await?
+        }
+        result => result?,
+    };
+
+    let (notifier, store, catch_up_threshold) = {
+        let data = data.lock().await;
+        (
+            data.notifier.clone(),
+            data.store.clone(),
+            data.catch_up_threshold,
+        )
+    };
+
+    let mut members_refreshed = false;
+    let mut pending_solves = Vec::new();
+
+    // HTB lists the most recent activity first, announce in the order it happened.
+    for solve in team_activity.into_iter().rev() {
         let solver_id = solve.user.id;
 
-        if let Some(previous_solves) = SOLVE_CACHE.get_mut(&solver_id) {
-            if previous_solves.contains(&solve) {
-                continue;
+        // Someone we haven't seen before, check whether they've just joined the team.
+        if !TEAM_MEMBERS.contains(&solver_id) && !members_refreshed {
+            members_refreshed = true;
+
+            if let Err(why) = refresh_team_members(&htb_api, &store).await {
+                log::error!("Error when refreshing team members: {why}");
             }
+        }
 
-            let challenge = Challenge {
-                name: solve.name.clone(),
-                machine_avatar: solve.machine_avatar.clone(),
-                points: solve.points,
-                challenge_type: solve.object_type.clone(),
-                challenge_category: solve.challenge_category.clone(),
-            };
-
-            let announce = SolveToAnnounce {
-                solver: solve.user.name.clone(),
-                user_id: solver_id,
-                solve_type: solve.solve_type.clone(),
-                challenge,
-            };
-
-            match announce_solve(&announce, &data.channel_id, &data.http).await {
-                Ok(()) => {
-                    previous_solves.insert(solve);
-                    num_new_solves += 1;
-                }
-                Err(why) => {
-                    log::error!("Error when announcing solve {announce:#?}, err: {why}");
+        if !TEAM_MEMBERS.contains(&solver_id) {
+            log::warn!(
+                "Skipping solve by {} ({solver_id}) as they aren't a member of the team",
+                solve.user.name
+            );
+            continue;
+        }
+
+        // Welcomed on their first solve, as that's what has their name and avatar.
+        if UNWELCOMED_MEMBERS.contains(&solver_id) {
+            let avatar_url = htb_api.config().avatar_url(&solve.user.avatar_thumb);
+
+            match notifier.welcome_member(&solve.user, &avatar_url).await {
+                Ok(()) => mark_welcomed(&store, solver_id),
+                Err(why) => log::error!("Error when welcoming {}, err: {why}", solve.user.name),
+            }
+        }
+
+        let key = SolveKey::from(&solve);
+
+        let already_announced = SOLVE_CACHE
+            .get(&solver_id)
+            .is_some_and(|previous_solves| previous_solves.contains(&key));
+
+        if !already_announced {
+            pending_solves.push(solve);
+        }
+    }
+
+    let mut num_new_solves = 0;
+
+    if pending_solves.len() > catch_up_threshold {
+        // First bloods still get their own announcement and ping, only the rest are digested.
+        let (bloods, digested): (Vec<_>, Vec<_>) = pending_solves
+            .into_iter()
+            .partition(|solve| solve.first_blood);
+        pending_solves = bloods;
+
+        log::info!(
+            "{} solves are waiting to be announced, posting a digest instead...",
+            digested.len() + pending_solves.len()
+        );
+
+        // The digested solves are tried again next poll, the bloods shouldn't wait on them.
+        if !digested.is_empty() {
+            match post_digest(notifier.as_ref(), &store, digested).await {
+                Ok(num_solves) => num_new_solves += num_solves,
+                Err(why) => log::error!("Error when posting a digest, err: {why}"),
+            }
+        }
+    }
+
+    for solve in pending_solves {
+        let announce = SolveToAnnounce {
+            solver: solve.user.name.clone(),
+            user_id: solve.user.id,
+            solve_type: solve.solve_type.clone(),
+            first_blood: solve.first_blood,
+            challenge: Challenge {
+                machine_avatar: solve
+                    .machine_avatar
+                    .as_deref()
+                    .map(|avatar| htb_api.config().avatar_url(avatar)),
+                ..Challenge::from(&solve)
+            },
+        };
+
+        match notifier.announce_solve(&announce).await {
+            Ok(message_id) => {
+                if let Err(why) = store.record(&solve, message_id) {
+                    log::error!("Error when persisting solve {announce:#?}, err: {why}");
                 }
+
+                cache_solve(SolveKey::from(&solve));
+                num_new_solves += 1;
+            }
+            Err(why) => {
+                log::error!("Error when announcing solve {announce:#?}, err: {why}");
             }
         }
     }
 
     Ok(num_new_solves)
 }
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        ops::RangeInclusive,
+        sync::{atomic::Ordering, Arc},
+    };
+
+    use async_mutex::Mutex;
+    use hackthebot::{
+        htb::fake::FakeHtbApi,
+        load_team_members,
+        notifier::{Notification, RecordingNotifier},
+        ScheduleRunnerData,
+    };
+    use tempfile::TempDir;
+
+    use super::*;
+
+    /// Adds copies of the latest solve with the given ids, with `blood_id` as a first blood.
+    fn add_solves(htb_api: &mut FakeHtbApi, ids: RangeInclusive<i64>, blood_id: Option<i64>) {
+        let template = htb_api.team_activity[0].clone();
+
+        for id in ids {
+            htb_api.team_activity.insert(
+                0,
+                RecentTeamSolve {
+                    id,
+                    first_blood: Some(id) == blood_id,
+                    ..template.clone()
+                },
+            );
+        }
+    }
+
+    fn join_team(htb_api: &mut FakeHtbApi, user_id: i64) {
+        let mut member = htb_api.team_members[0].clone();
+        member.id = user_id;
+        htb_api.team_members.push(member);
+    }
+
+    fn add_solve_by(htb_api: &mut FakeHtbApi, user_id: i64, name: &str) {
+        let mut solve = htb_api.team_activity[0].clone();
+        solve.user.id = user_id;
+        solve.user.name = name.to_owned();
+        htb_api.team_activity.insert(0, solve);
+    }
+
+    // Everything that touches the global caches lives in this one test, so nothing races.
+    #[tokio::test]
+    async fn test_process_new_solves() {
+        let store_dir = TempDir::new().unwrap();
+        let store_path = store_dir.path().join("solves.jsonl");
+
+        let notifier = Arc::new(RecordingNotifier::default());
+        let data = Arc::new(Mutex::new(ScheduleRunnerData {
+            htb_api: FakeHtbApi::new(),
+            notifier: notifier.clone(),
+            store: SolveStore::new(&store_path),
+            catch_up_threshold: 2,
+        }));
+
+        // The first poll only learns who is in the team, so nobody is welcomed.
+        assert_eq!(process_new_solves(&data).await.unwrap(), 2);
+        let notifications = notifier.take();
+        assert_eq!(notifications.len(), 2);
+        assert!(
+            matches!(&notifications[0], Notification::Solve(solve) if solve.challenge.name == "Ophiuchi")
+        );
+
+        assert_eq!(process_new_solves(&data).await.unwrap(), 0);
+        assert!(notifier.take().is_empty());
+
+        // Someone joins the team and solves something straight away.
+        // Two people join the team, only one of them solves something straight away.
+        {
+            let mut data = data.lock().await;
+            join_team(&mut data.htb_api, 1338);
+            join_team(&mut data.htb_api, 1337);
+            add_solve_by(&mut data.htb_api, 1337, "newbie");
+        }
+
+        assert_eq!(process_new_solves(&data).await.unwrap(), 1);
+        let notifications = notifier.take();
+        assert!(matches!(&notifications[..], [
+            Notification::Welcome(user),
+            Notification::Solve(solve),
+        ] if user.id == 1337 && solve.user_id == 1337));
+
+        // The other is welcomed whenever they get round to it.
+        add_solve_by(&mut data.lock().await.htb_api, 1338, "lurker");
+
+        assert_eq!(process_new_solves(&data).await.unwrap(), 1);
+        let notifications = notifier.take();
+        assert!(matches!(&notifications[..], [
+            Notification::Welcome(user),
+            Notification::Solve(solve),
+        ] if user.id == 1338 && solve.user_id == 1338));
+        assert!(UNWELCOMED_MEMBERS.is_empty());
+
+        // Too many solves at once are posted as a digest, apart from first bloods.
+        add_solves(&mut data.lock().await.htb_api, 1..=4, Some(2));
+
+        assert_eq!(process_new_solves(&data).await.unwrap(), 4);
+        assert!(matches!(&notifier.take()[..], [
+            Notification::Digest(solves),
+            Notification::Solve(blood),
+        ] if solves.len() == 3 && solves.iter().all(|solve| !solve.first_blood) && blood.first_blood));
+
+        // Every solve made it into the store, including the ones from the digest.
+        let stored = SolveStore::new(&store_path).load().unwrap();
+        assert_eq!(stored.len(), 8);
+
+        // A first blood on its own is announced as one.
+        add_solves(&mut data.lock().await.htb_api, 5..=5, Some(5));
+
+        assert_eq!(process_new_solves(&data).await.unwrap(), 1);
+        assert!(matches!(&notifier.take()[..], [Notification::Solve(blood)] if blood.first_blood));
+
+        // A digest that can't be posted doesn't hold back the first bloods, and is tried again.
+        add_solves(&mut data.lock().await.htb_api, 6..=9, Some(9));
+
+        notifier.fail_digests.store(true, Ordering::Relaxed);
+        assert_eq!(process_new_solves(&data).await.unwrap(), 1);
+        assert!(matches!(&notifier.take()[..], [Notification::Solve(blood)] if blood.first_blood));
+
+        notifier.fail_digests.store(false, Ordering::Relaxed);
+        assert_eq!(process_new_solves(&data).await.unwrap(), 3);
+        assert!(
+            matches!(&notifier.take()[..], [Notification::Digest(solves)] if solves.len() == 3)
+        );
+
+        // Someone joins while the bot is restarting, they're still welcomed once it's back.
+        TEAM_MEMBERS.clear();
+        {
+            let mut data = data.lock().await;
+            join_team(&mut data.htb_api, 4242);
+            add_solve_by(&mut data.htb_api, 4242, "latecomer");
+            load_team_members(&data.htb_api, &data.store).await.unwrap();
+        }
+        assert!(!TEAM_MEMBERS.contains(&4242));
+
+        assert_eq!(process_new_solves(&data).await.unwrap(), 1);
+        assert!(matches!(&notifier.take()[..], [
+            Notification::Welcome(user),
+            Notification::Solve(solve),
+        ] if user.id == 4242 && solve.user_id == 4242));
+
+        process_rank_status(&data).await.unwrap();
+        assert_eq!(
+            notifier.take(),
+            vec![Notification::Status {
+                rank: 381,
+                points: 101
+            }]
+        );
+    }
+}